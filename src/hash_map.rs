@@ -0,0 +1,494 @@
+use std::sync::Arc;
+use std::default::Default;
+use std::borrow::BorrowFrom;
+use std::fmt;
+use std::hash::{Hash, Hasher, RandomSipHasher};
+use std::iter::FromIterator;
+use std::slice;
+
+use map::{Arcs, Traversal};
+
+use self::Node::{Branch, Leaf, Collision};
+use self::Change::{Unchanged, Replaced};
+
+// How many bits of a hash are used to pick a child at each level.
+static BITS: uint = 5;
+
+// Selects the bits of a hash used at one level, once they are shifted down.
+static MASK: u64 = 31;
+
+/// A key value store, implemented as a persistent, functional hash array
+/// mapped trie.
+///
+/// Unlike `Map`, keys only need to be hashable and comparable for equality.
+/// Lookups, insertions and deletions walk at most one node for every five
+/// bits of a key's hash, and copy only the nodes on that path, so versions
+/// of a map share all of their other nodes.
+pub struct HashMap<K, V, H = RandomSipHasher> {
+    root: Arc<Node<K, V>>,
+    size: uint,
+    hasher: H
+}
+
+// A node of the trie.
+enum Node<K, V> {
+    // An inner node, which has a child for every bit set in its bitmap, in
+    // order of those bits.
+    Branch {
+        bitmap: u32,
+        children: Vec<Arc<Node<K, V>>>
+    },
+
+    // A single pair, along with the full hash of its key.
+    Leaf {
+        hash: u64,
+        key: Arc<K>,
+        value: Arc<V>
+    },
+
+    // At least two pairs whose keys have the same full hash.
+    Collision {
+        hash: u64,
+        pairs: Vec<(Arc<K>, Arc<V>)>
+    }
+}
+
+// What altering a subtrie did to it.
+enum Change<K, V> {
+    // Nothing changed, so the subtrie can be shared as it is.
+    Unchanged,
+
+    // The subtrie was replaced, or removed if None, and the size of the map
+    // changed by this much.
+    Replaced(Option<Arc<Node<K, V>>>, int)
+}
+
+impl<K: Send + Sync, V: Send + Sync, H> HashMap<K, V, H> {
+    /// How many pairs are in the map.
+    #[inline]
+    pub fn len(&self) -> uint { self.size }
+
+    /// Is the map empty?
+    #[inline]
+    pub fn is_empty(&self) -> bool { self.size == 0 }
+
+    /// Get an iterator over the pairs of the map, in no particular order.
+    pub fn iter<'a>(&'a self) -> HashItems<'a, K, V> {
+        HashItems {
+            stack: vec![&*self.root],
+            pairs: None,
+            remaining: self.size
+        }
+    }
+}
+
+impl<K: Eq + Hash + Send + Sync, V: Send + Sync> HashMap<K, V, RandomSipHasher> {
+    /// An empty map, using a randomly keyed SipHash hasher.
+    #[inline]
+    pub fn new() -> HashMap<K, V, RandomSipHasher> {
+        HashMap::with_hasher(RandomSipHasher::new())
+    }
+}
+
+impl<K: Eq + Hash<S> + Send + Sync, V: Send + Sync, S, H: Hasher<S> + Clone> HashMap<K, V, H> {
+    /// An empty map which hashes its keys with `hasher`.
+    ///
+    /// Every version of the map made from this one uses the same hasher.
+    pub fn with_hasher(hasher: H) -> HashMap<K, V, H> {
+        HashMap {
+            root: Arc::new(Branch { bitmap: 0, children: Vec::new() }),
+            size: 0,
+            hasher: hasher
+        }
+    }
+
+    /// Get the value at a key in the map.
+    pub fn get<'a, Sized? Q>(&'a self, key: &Q) -> Option<&'a V>
+            where Q: Eq + Hash<S> + BorrowFrom<K> {
+        let hash = self.hasher.hash(key);
+        let mut node = &*self.root;
+        let mut shift = 0;
+
+        loop {
+            match *node {
+                Branch { bitmap, ref children } => {
+                    let bit = 1u32 << ((hash >> shift) & MASK) as uint;
+                    if bitmap & bit == 0 { return None }
+
+                    node = &*children[(bitmap & (bit - 1)).count_ones()];
+                    shift += BITS;
+                },
+                Leaf { hash: h, key: ref k, ref value } => {
+                    return if h == hash && key.eq(BorrowFrom::borrow_from(&**k)) {
+                        Some(&**value)
+                    } else {
+                        None
+                    }
+                },
+                Collision { hash: h, ref pairs } => {
+                    if h != hash { return None }
+
+                    return pairs.iter()
+                        .find(|&&(ref k, _)| key.eq(BorrowFrom::borrow_from(&**k)))
+                        .map(|&(_, ref value)| &**value)
+                }
+            }
+        }
+    }
+
+    /// Is this key a member of the map?
+    #[inline]
+    pub fn contains<Sized? Q>(&self, key: &Q) -> bool where Q: Eq + Hash<S> + BorrowFrom<K> {
+        self.get(key).is_some()
+    }
+
+    /// Insert a key value pair into the map. If the key is already present,
+    /// the pair in the map is replaced.
+    pub fn insert(&self, key: Arc<K>, value: Arc<V>) -> HashMap<K, V, H> {
+        self.modify(&*key, &mut |_| Some((key.clone(), value.clone())))
+    }
+
+    /// Delete a key and its value from the map.
+    ///
+    /// If the key is not a member of the map, the original map is returned.
+    pub fn delete<Sized? Q>(&self, key: &Q) -> HashMap<K, V, H>
+            where Q: Eq + Hash<S> + BorrowFrom<K> {
+        self.modify(key, &mut |_| None)
+    }
+
+    /// Remove a key from the map, returning the removed pair and the new map.
+    ///
+    /// Returns None if the key is not a member of the map.
+    pub fn remove<Sized? Q>(&self, key: &Q) -> Option<((Arc<K>, Arc<V>), HashMap<K, V, H>)>
+            where Q: Eq + Hash<S> + BorrowFrom<K> {
+        let mut removed = None;
+        let map = self.modify(key, &mut |found| {
+            removed = found.map(|(k, v)| (k.clone(), v.clone()));
+            None
+        });
+
+        removed.map(|pair| (pair, map))
+    }
+
+    /// Modify the value at a key in the map.
+    ///
+    /// If the key is not a member of the map, the original map is returned.
+    pub fn adjust<Sized? Q>(&self, key: &Q, modifier: |&V| -> V) -> HashMap<K, V, H>
+            where Q: Eq + Hash<S> + BorrowFrom<K> {
+        self.modify(key, &mut |found| {
+            found.map(|(k, v)| (k.clone(), Arc::new(modifier(&**v))))
+        })
+    }
+
+    /// Modify or delete the value at a key in the map.
+    ///
+    /// If the closure returns None, the key is deleted. If the key is not a
+    /// member of the map, the original map is returned.
+    pub fn update<Sized? Q>(&self, key: &Q, modifier: |&V| -> Option<V>) -> HashMap<K, V, H>
+            where Q: Eq + Hash<S> + BorrowFrom<K> {
+        self.modify(key, &mut |found| {
+            found.and_then(|(k, v)| modifier(&**v).map(|value| (k.clone(), Arc::new(value))))
+        })
+    }
+
+    /// Insert, modify or delete the value at a key in the map.
+    ///
+    /// Like `Map::alter`, the closure is called with the key and value if the
+    /// key is found and with None otherwise, and returns the new value, or
+    /// None to delete the key or leave it not inserted.
+    pub fn alter(&self, key: Arc<K>, modifier: |Option<&K>, Option<&V>| -> Option<V>)
+            -> HashMap<K, V, H> {
+        self.modify(&*key, &mut |found| {
+            match found {
+                Some((k, v)) => {
+                    modifier(Some(&**k), Some(&**v)).map(|value| (k.clone(), Arc::new(value)))
+                },
+                None => modifier(None, None).map(|value| (key.clone(), Arc::new(value)))
+            }
+        })
+    }
+
+    // Alter the pair at a key, sharing this map if nothing changed.
+    fn modify<Sized? Q>(&self, key: &Q,
+                        f: &mut |Option<(&Arc<K>, &Arc<V>)>| -> Option<(Arc<K>, Arc<V>)>)
+            -> HashMap<K, V, H> where Q: Eq + Hash<S> + BorrowFrom<K> {
+        match alter_node(&self.root, self.hasher.hash(key), 0, key, f) {
+            Unchanged => self.clone(),
+            Replaced(root, delta) => HashMap {
+                // The root is a branch, which is never removed.
+                root: root.unwrap_or_else(|| Arc::new(Branch { bitmap: 0, children: Vec::new() })),
+                size: (self.size as int + delta) as uint,
+                hasher: self.hasher.clone()
+            }
+        }
+    }
+}
+
+// Alter the pair at a key in the subtrie below `node`, which uses the bits
+// of the hash from `shift` upwards to pick children.
+//
+// The closure is called with the pair at the key if there is one, and
+// returns the new pair, or None to remove it.
+fn alter_node<K: Eq + Send + Sync, V: Send + Sync, Sized? Q>(
+        node: &Arc<Node<K, V>>, hash: u64, shift: uint, key: &Q,
+        f: &mut |Option<(&Arc<K>, &Arc<V>)>| -> Option<(Arc<K>, Arc<V>)>) -> Change<K, V>
+        where Q: Eq + BorrowFrom<K> {
+    match **node {
+        Branch { bitmap, ref children } => {
+            let bit = 1u32 << ((hash >> shift) & MASK) as uint;
+            let index = (bitmap & (bit - 1)).count_ones();
+
+            if bitmap & bit == 0 {
+                return match (*f)(None) {
+                    None => Unchanged,
+                    Some((key, value)) => {
+                        let mut children = children.clone();
+                        children.insert(index, Arc::new(Leaf { hash: hash, key: key, value: value }));
+                        Replaced(Some(Arc::new(Branch { bitmap: bitmap | bit, children: children })), 1)
+                    }
+                }
+            }
+
+            match alter_node(&children[index], hash, shift + BITS, key, f) {
+                Unchanged => Unchanged,
+                Replaced(Some(child), delta) => {
+                    let mut children = children.clone();
+                    children[index] = child;
+                    Replaced(Some(compact(bitmap, children, shift)), delta)
+                },
+                Replaced(None, delta) => {
+                    let mut children = children.clone();
+                    children.remove(index);
+
+                    if children.is_empty() && shift > 0 {
+                        Replaced(None, delta)
+                    } else {
+                        Replaced(Some(compact(bitmap & !bit, children, shift)), delta)
+                    }
+                }
+            }
+        },
+        Leaf { hash: h, key: ref k, value: ref v } => {
+            if h == hash && key.eq(BorrowFrom::borrow_from(&**k)) {
+                return match (*f)(Some((k, v))) {
+                    None => Replaced(None, -1),
+                    Some((key, value)) => {
+                        Replaced(Some(Arc::new(Leaf { hash: h, key: key, value: value })), 0)
+                    }
+                }
+            }
+
+            match (*f)(None) {
+                None => Unchanged,
+                Some((key, value)) => {
+                    let added = if h == hash {
+                        Collision { hash: h, pairs: vec![(k.clone(), v.clone()), (key, value)] }
+                    } else {
+                        let leaf = Arc::new(Leaf { hash: hash, key: key, value: value });
+                        branch_of_two(node.clone(), h, leaf, hash, shift)
+                    };
+                    Replaced(Some(Arc::new(added)), 1)
+                }
+            }
+        },
+        Collision { hash: h, ref pairs } => {
+            if h != hash {
+                return match (*f)(None) {
+                    None => Unchanged,
+                    Some((key, value)) => {
+                        let leaf = Arc::new(Leaf { hash: hash, key: key, value: value });
+                        Replaced(Some(Arc::new(branch_of_two(node.clone(), h, leaf, hash, shift))), 1)
+                    }
+                }
+            }
+
+            let mut pairs = pairs.clone();
+            let position = pairs.iter().position(|&(ref k, _)| key.eq(BorrowFrom::borrow_from(&**k)));
+
+            let delta = match position {
+                Some(index) => {
+                    let found = {
+                        let (ref k, ref v) = pairs[index];
+                        (*f)(Some((k, v)))
+                    };
+
+                    match found {
+                        Some(pair) => { pairs[index] = pair; 0 },
+                        None => { pairs.remove(index); -1 }
+                    }
+                },
+                None => match (*f)(None) {
+                    Some(pair) => { pairs.push(pair); 1 },
+                    None => return Unchanged
+                }
+            };
+
+            // A collision node always holds at least two pairs.
+            let altered = if pairs.len() == 1 {
+                let (key, value) = pairs.pop().unwrap();
+                Leaf { hash: h, key: key, value: value }
+            } else {
+                Collision { hash: h, pairs: pairs }
+            };
+            Replaced(Some(Arc::new(altered)), delta)
+        }
+    }
+}
+
+// Build a branch from a bitmap and its children. Below the root, a branch
+// with a single leaf or collision child is replaced by that child, so that
+// deletions shrink the trie back to the shape insertions would give it.
+fn compact<K: Send + Sync, V: Send + Sync>(bitmap: u32, children: Vec<Arc<Node<K, V>>>, shift: uint)
+        -> Arc<Node<K, V>> {
+    if shift > 0 && children.len() == 1 {
+        match *children[0] {
+            Branch { .. } => {},
+            Leaf { .. } | Collision { .. } => return children[0].clone()
+        }
+    }
+
+    Arc::new(Branch { bitmap: bitmap, children: children })
+}
+
+// Build the branches holding two nodes with different hashes, starting at
+// the level which uses the bits of the hash from `shift` upwards.
+fn branch_of_two<K: Send + Sync, V: Send + Sync>(a: Arc<Node<K, V>>, a_hash: u64,
+                                                 b: Arc<Node<K, V>>, b_hash: u64, shift: uint) -> Node<K, V> {
+    let a_index = ((a_hash >> shift) & MASK) as uint;
+    let b_index = ((b_hash >> shift) & MASK) as uint;
+
+    if a_index == b_index {
+        // The hashes differ somewhere above these bits, so this terminates.
+        Branch {
+            bitmap: 1 << a_index,
+            children: vec![Arc::new(branch_of_two(a, a_hash, b, b_hash, shift + BITS))]
+        }
+    } else {
+        Branch {
+            bitmap: (1 << a_index) | (1 << b_index),
+            children: if a_index < b_index { vec![a, b] } else { vec![b, a] }
+        }
+    }
+}
+
+impl<K: Send + Sync, V: Send + Sync, H: Clone> Clone for HashMap<K, V, H> {
+    fn clone(&self) -> HashMap<K, V, H> {
+        HashMap {
+            root: self.root.clone(),
+            size: self.size,
+            hasher: self.hasher.clone()
+        }
+    }
+}
+
+impl<K: Eq + Hash<S> + Send + Sync, V: Send + Sync, S, H: Hasher<S> + Clone + Default> Default
+        for HashMap<K, V, H> {
+    #[inline]
+    fn default() -> HashMap<K, V, H> { HashMap::with_hasher(Default::default()) }
+}
+
+// Comparisons
+//
+// Maps with the same pairs can have different hashers and so different
+// shapes, so every pair of one map is looked up in the other.
+impl<K: Eq + Hash<S> + Send + Sync, V: PartialEq + Send + Sync, S, H: Hasher<S> + Clone> PartialEq
+        for HashMap<K, V, H> {
+    fn eq(&self, other: &HashMap<K, V, H>) -> bool {
+        if &*self.root as *const Node<K, V> == &*other.root as *const Node<K, V> { return true }
+
+        self.len() == other.len() && self.iter().all(|(key, value)| other.get(key) == Some(value))
+    }
+}
+
+impl<K: Eq + Hash<S> + Send + Sync, V: Eq + Send + Sync, S, H: Hasher<S> + Clone> Eq for HashMap<K, V, H> {}
+
+impl<K: fmt::Show + Send + Sync, V: fmt::Show + Send + Sync, H> fmt::Show for HashMap<K, V, H> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{{"));
+
+        for (i, (key, value)) in self.iter().enumerate() {
+            if i != 0 { try!(write!(f, ", ")); }
+            try!(write!(f, "{}: {}", *key, *value));
+        }
+
+        write!(f, "}}")
+    }
+}
+
+impl<K: Eq + Hash<S> + Send + Sync, V: Send + Sync, S, H: Hasher<S> + Clone + Default> FromIterator<(K, V)>
+        for HashMap<K, V, H> {
+    /// Build a map from an iterator of pairs. Later values replace earlier
+    /// ones.
+    fn from_iter<I: Iterator<(K, V)>>(iter: I) -> HashMap<K, V, H> {
+        let mut map = HashMap::with_hasher(Default::default());
+        map.extend(iter);
+        map
+    }
+}
+
+impl<K: Eq + Hash<S> + Send + Sync, V: Send + Sync, S, H: Hasher<S> + Clone> Extend<(K, V)>
+        for HashMap<K, V, H> {
+    /// Add pairs from an iterator to the map, replacing the values of keys
+    /// which are already present.
+    fn extend<I: Iterator<(K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            *self = self.insert(Arc::new(key), Arc::new(value));
+        }
+    }
+}
+
+/// An iterator over the pairs of a hash map, in no particular order.
+pub struct HashItems<'a, K: 'a, V: 'a> {
+    // Nodes which have yet to be visited.
+    stack: Vec<&'a Node<K, V>>,
+
+    // The rest of the collision node being visited, if any.
+    pairs: Option<slice::Items<'a, (Arc<K>, Arc<V>)>>,
+
+    remaining: uint
+}
+
+impl<'a, K: Send + Sync, V: Send + Sync> HashItems<'a, K, V> {
+    /// Adapt this iterator to yield cloned `Arc` pairs instead of references.
+    #[inline]
+    pub fn arcs(self) -> Arcs<HashItems<'a, K, V>> { Arcs::new(self) }
+}
+
+impl<'a, K: Send + Sync, V: Send + Sync> Traversal<'a, K, V> for HashItems<'a, K, V> {
+    fn next_pair(&mut self) -> Option<(&'a Arc<K>, &'a Arc<V>)> {
+        loop {
+            match self.pairs.as_mut().and_then(|pairs| pairs.next()) {
+                Some(&(ref key, ref value)) => {
+                    self.remaining -= 1;
+                    return Some((key, value))
+                },
+                None => {}
+            }
+
+            match self.stack.pop() {
+                None => return None,
+                Some(&Branch { ref children, .. }) => {
+                    self.stack.extend(children.iter().rev().map(|child| &**child));
+                },
+                Some(&Leaf { ref key, ref value, .. }) => {
+                    self.remaining -= 1;
+                    return Some((key, value))
+                },
+                Some(&Collision { ref pairs, .. }) => self.pairs = Some(pairs.iter())
+            }
+        }
+    }
+
+    #[inline]
+    fn remaining(&self) -> uint { self.remaining }
+}
+
+impl<'a, K: Send + Sync, V: Send + Sync> Iterator<(&'a K, &'a V)> for HashItems<'a, K, V> {
+    fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        self.next_pair().map(|(key, value)| (&**key, &**value))
+    }
+
+    fn size_hint(&self) -> (uint, Option<uint>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
@@ -0,0 +1,456 @@
+use std::sync::Arc;
+use std::default::Default;
+use std::fmt;
+use std::iter::FromIterator;
+use std::mem;
+use alloc::arc;
+
+pub use self::Heap::{Node, Empty};
+
+/// A persistent, functional min-heap, implemented as a leftist heap.
+///
+/// The rank of a node is the length of its right spine, and every node's
+/// left branch has at least the rank of its right branch. Merging only walks
+/// down right spines, so pushing, popping and merging all take logarithmic
+/// time, and share every branch they do not walk down.
+pub enum Heap<T> {
+    /// A node of the heap.
+    Node {
+        /// The length of the right spine of this node.
+        rank: uint,
+
+        /// The size of this node.
+        size: uint,
+
+        /// The value at this node, which is no greater than any value in its
+        /// branches.
+        value: Arc<T>,
+
+        /// The branch with the longer right spine.
+        left: Arc<Heap<T>>,
+
+        /// The branch with the shorter right spine.
+        right: Arc<Heap<T>>
+    },
+
+    /// The empty heap.
+    Empty
+}
+
+impl<T: Send + Sync> Clone for Heap<T> {
+    fn clone(&self) -> Heap<T> {
+        match *self {
+            Node { rank, size, ref value, ref left, ref right } => Node {
+                rank: rank,
+                size: size,
+                value: value.clone(),
+                left: left.clone(),
+                right: right.clone()
+            },
+            Empty => Empty
+        }
+    }
+}
+
+// The longer spine of a heap is on the left, and pushing values in
+// descending order makes it as long as the heap, so the drop glue, which
+// recurses once for every node on it, can overflow the stack. Instead,
+// branches which nothing else refers to are unlinked from their nodes and
+// dropped one at a time.
+#[unsafe_destructor]
+impl<T: Send + Sync> Drop for Heap<T> {
+    fn drop(&mut self) {
+        let mut pending = Vec::new();
+        let mut empty = None;
+
+        match *self {
+            Node { ref mut left, ref mut right, .. } => {
+                unlink(left, &mut empty, &mut pending);
+                unlink(right, &mut empty, &mut pending);
+            },
+            Empty => return
+        }
+
+        loop {
+            let mut heap = match pending.pop() {
+                Some(heap) => heap,
+                None => break
+            };
+
+            match arc::get_mut(&mut heap) {
+                Some(&mut Node { ref mut left, ref mut right, .. }) => {
+                    unlink(left, &mut empty, &mut pending);
+                    unlink(right, &mut empty, &mut pending);
+                },
+                _ => {}
+            }
+
+            // Drops the node, whose branches are now empty or shared.
+        }
+    }
+}
+
+// Move a branch onto `pending` if this is the only reference to it, leaving
+// a shared empty heap in its place. Empty branches are left alone, so
+// dropping an unlinked node does not allocate.
+fn unlink<T: Send + Sync>(branch: &mut Arc<Heap<T>>, empty: &mut Option<Arc<Heap<T>>>,
+                          pending: &mut Vec<Arc<Heap<T>>>) {
+    if branch.is_empty() || arc::get_mut(branch).is_none() { return }
+
+    if empty.is_none() { *empty = Some(Arc::new(Empty)); }
+    pending.push(mem::replace(branch, empty.as_ref().unwrap().clone()));
+}
+
+impl<T: Send + Sync> Default for Heap<T> {
+    #[inline]
+    fn default() -> Heap<T> { Heap::new() }
+}
+
+impl<T: Send + Sync> Heap<T> {
+    /// An empty heap.
+    #[inline]
+    pub fn new() -> Heap<T> { Empty }
+
+    /// Create a heap with one value.
+    #[inline]
+    pub fn singleton(value: T) -> Heap<T> { Heap::singleton_arc(Arc::new(value)) }
+
+    fn singleton_arc(value: Arc<T>) -> Heap<T> {
+        Node {
+            rank: 1,
+            size: 1,
+            value: value,
+            left: Arc::new(Empty),
+            right: Arc::new(Empty)
+        }
+    }
+
+    /// How many values are in the heap.
+    #[inline]
+    pub fn len(&self) -> uint {
+        match *self {
+            Node { size, .. } => size,
+            Empty => 0
+        }
+    }
+
+    /// Is the heap empty?
+    #[inline]
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+
+    /// Get the smallest value in the heap.
+    #[inline]
+    pub fn peek(&self) -> Option<&T> {
+        match *self {
+            Node { ref value, .. } => Some(&**value),
+            Empty => None
+        }
+    }
+
+    // The rank of a heap, which is zero for the empty heap.
+    #[inline]
+    fn rank(&self) -> uint {
+        match *self {
+            Node { rank, .. } => rank,
+            Empty => 0
+        }
+    }
+
+    // Node constructor which takes care of rank and size, and puts the
+    // branch with the longer right spine on the left.
+    fn node(value: Arc<T>, a: Arc<Heap<T>>, b: Arc<Heap<T>>) -> Heap<T> {
+        let (left, right) = if a.rank() >= b.rank() { (a, b) } else { (b, a) };
+
+        Node {
+            rank: right.rank() + 1,
+            size: left.len() + right.len() + 1,
+            value: value,
+            left: left,
+            right: right
+        }
+    }
+
+    // Merge two heaps, where `le(a, b)` says whether `a` may come out of the
+    // heap before `b`.
+    fn merge_by(a: &Heap<T>, b: &Heap<T>, le: &mut |&T, &T| -> bool) -> Heap<T> {
+        match (a, b) {
+            (&Empty, _) => b.clone(),
+            (_, &Empty) => a.clone(),
+            (&Node { value: ref x, left: ref lx, right: ref rx, .. },
+             &Node { value: ref y, left: ref ly, right: ref ry, .. }) => {
+                if (*le)(&**x, &**y) {
+                    Heap::node(x.clone(), lx.clone(), Arc::new(Heap::merge_by(&**rx, b, le)))
+                } else {
+                    Heap::node(y.clone(), ly.clone(), Arc::new(Heap::merge_by(a, &**ry, le)))
+                }
+            }
+        }
+    }
+
+    // Remove the first value to come out of the heap, by `le` as above.
+    fn pop_by(&self, le: &mut |&T, &T| -> bool) -> Option<(Arc<T>, Heap<T>)> {
+        match *self {
+            Node { ref value, ref left, ref right, .. } => {
+                Some((value.clone(), Heap::merge_by(&**left, &**right, le)))
+            },
+            Empty => None
+        }
+    }
+}
+
+impl<T: Ord + Send + Sync> Heap<T> {
+    /// Create a new heap with a value added to this one.
+    pub fn push(&self, value: Arc<T>) -> Heap<T> {
+        Heap::merge_by(self, &Heap::singleton_arc(value), &mut |a, b| a <= b)
+    }
+
+    /// Remove the smallest value from the heap, returning it and the new
+    /// heap.
+    ///
+    /// Returns None if the heap is empty.
+    pub fn pop(&self) -> Option<(Arc<T>, Heap<T>)> { self.pop_by(&mut |a, b| a <= b) }
+
+    /// Merge two heaps.
+    pub fn merge(&self, other: &Heap<T>) -> Heap<T> {
+        Heap::merge_by(self, other, &mut |a, b| a <= b)
+    }
+
+    /// Get an iterator over the values of the heap, in ascending order.
+    ///
+    /// Each step pops a value from a copy of the heap, so it takes
+    /// logarithmic time.
+    #[inline]
+    pub fn iter(&self) -> HeapItems<T> { HeapItems { heap: self.clone() } }
+}
+
+impl<T: Ord + Send + Sync + fmt::Show> fmt::Show for Heap<T> {
+    /// Format the values of the heap in ascending order.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "["));
+
+        for (i, value) in self.iter().enumerate() {
+            if i != 0 { try!(write!(f, ", ")); }
+            try!(write!(f, "{}", *value));
+        }
+
+        write!(f, "]")
+    }
+}
+
+impl<T: Ord + Send + Sync> FromIterator<T> for Heap<T> {
+    /// Build a heap from the values of an iterator, in linear time.
+    ///
+    /// The values are merged in pairs, then the merged heaps in pairs, and
+    /// so on, rather than pushed one at a time.
+    fn from_iter<I: Iterator<T>>(iter: I) -> Heap<T> {
+        let mut heaps: Vec<Heap<T>> = iter.map(|value| Heap::singleton(value)).collect();
+
+        while heaps.len() > 1 {
+            let mut merged = Vec::with_capacity((heaps.len() + 1) / 2);
+
+            for pair in heaps.as_slice().chunks(2) {
+                merged.push(if pair.len() == 2 { pair[0].merge(&pair[1]) } else { pair[0].clone() });
+            }
+
+            heaps = merged;
+        }
+
+        heaps.pop().unwrap_or(Empty)
+    }
+}
+
+impl<T: Ord + Send + Sync> Extend<T> for Heap<T> {
+    /// Add the values of an iterator to the heap.
+    ///
+    /// The new values are built into a heap of their own and then merged
+    /// with this one.
+    fn extend<I: Iterator<T>>(&mut self, iter: I) {
+        let new: Heap<T> = iter.collect();
+        *self = self.merge(&new);
+    }
+}
+
+/// An iterator over the values of a heap, in ascending order.
+pub struct HeapItems<T> {
+    heap: Heap<T>
+}
+
+impl<T: Ord + Send + Sync> Iterator<Arc<T>> for HeapItems<T> {
+    fn next(&mut self) -> Option<Arc<T>> {
+        match self.heap.pop() {
+            Some((value, rest)) => {
+                self.heap = rest;
+                Some(value)
+            },
+            None => None
+        }
+    }
+
+    fn size_hint(&self) -> (uint, Option<uint>) {
+        (self.heap.len(), Some(self.heap.len()))
+    }
+}
+
+/// An ordering of priorities, for use by a priority queue.
+pub trait Compare<T> {
+    /// Compare two priorities. The queue pops whichever priority is `Less`
+    /// first.
+    fn compare(&self, a: &T, b: &T) -> Ordering;
+}
+
+/// Orders priorities from smallest to largest.
+#[deriving(Clone, Default, Show)]
+pub struct Ascending;
+
+impl<T: Ord> Compare<T> for Ascending {
+    #[inline]
+    fn compare(&self, a: &T, b: &T) -> Ordering { a.cmp(b) }
+}
+
+/// Orders priorities from largest to smallest.
+#[deriving(Clone, Default, Show)]
+pub struct Descending;
+
+impl<T: Ord> Compare<T> for Descending {
+    #[inline]
+    fn compare(&self, a: &T, b: &T) -> Ordering { b.cmp(a) }
+}
+
+/// A persistent priority queue, built on a heap.
+///
+/// Pairs of priorities and values are popped in the order given by the
+/// comparator, which pops the smallest priority first by default. Pairs with
+/// equal priorities are popped in no particular order.
+pub struct PriorityQueue<P, V, C = Ascending> {
+    heap: Heap<(P, V)>,
+    comparator: C
+}
+
+impl<P: Ord + Send + Sync, V: Send + Sync> PriorityQueue<P, V, Ascending> {
+    /// An empty queue which pops the smallest priority first.
+    #[inline]
+    pub fn new() -> PriorityQueue<P, V, Ascending> { PriorityQueue::with_comparator(Ascending) }
+}
+
+impl<P: Send + Sync, V: Send + Sync, C: Compare<P> + Clone> PriorityQueue<P, V, C> {
+    /// An empty queue which orders its priorities with `comparator`.
+    ///
+    /// Every version of the queue made from this one uses the same
+    /// comparator.
+    #[inline]
+    pub fn with_comparator(comparator: C) -> PriorityQueue<P, V, C> {
+        PriorityQueue {
+            heap: Empty,
+            comparator: comparator
+        }
+    }
+
+    /// How many pairs are in the queue.
+    #[inline]
+    pub fn len(&self) -> uint { self.heap.len() }
+
+    /// Is the queue empty?
+    #[inline]
+    pub fn is_empty(&self) -> bool { self.heap.is_empty() }
+
+    /// Get the priority and value which will be popped next.
+    pub fn peek(&self) -> Option<(&P, &V)> {
+        self.heap.peek().map(|&(ref priority, ref value)| (priority, value))
+    }
+
+    /// Create a new queue with a pair added to this one.
+    pub fn push(&self, priority: P, value: V) -> PriorityQueue<P, V, C> {
+        let single = Heap::singleton((priority, value));
+        self.with_heap(Heap::merge_by(&self.heap, &single, &mut |a, b| self.le(a, b)))
+    }
+
+    /// Remove the next pair from the queue, returning it and the new queue.
+    ///
+    /// Returns None if the queue is empty.
+    pub fn pop(&self) -> Option<(&P, &V, PriorityQueue<P, V, C>)> {
+        let rest = match self.heap.pop_by(&mut |a, b| self.le(a, b)) {
+            Some((_, rest)) => rest,
+            None => return None
+        };
+
+        self.peek().map(|(priority, value)| (priority, value, self.with_heap(rest)))
+    }
+
+    /// Merge two queues, using the comparator of this one.
+    pub fn merge(&self, other: &PriorityQueue<P, V, C>) -> PriorityQueue<P, V, C> {
+        self.with_heap(Heap::merge_by(&self.heap, &other.heap, &mut |a, b| self.le(a, b)))
+    }
+
+    // Does this pair come out of the queue no later than that one?
+    fn le(&self, &(ref a, _): &(P, V), &(ref b, _): &(P, V)) -> bool {
+        self.comparator.compare(a, b) != Greater
+    }
+
+    fn with_heap(&self, heap: Heap<(P, V)>) -> PriorityQueue<P, V, C> {
+        PriorityQueue {
+            heap: heap,
+            comparator: self.comparator.clone()
+        }
+    }
+}
+
+impl<P: Send + Sync, V: Send + Sync, C: Clone> Clone for PriorityQueue<P, V, C> {
+    fn clone(&self) -> PriorityQueue<P, V, C> {
+        PriorityQueue {
+            heap: self.heap.clone(),
+            comparator: self.comparator.clone()
+        }
+    }
+}
+
+impl<P: Send + Sync, V: Send + Sync, C: Compare<P> + Clone + Default> Default for PriorityQueue<P, V, C> {
+    #[inline]
+    fn default() -> PriorityQueue<P, V, C> { PriorityQueue::with_comparator(Default::default()) }
+}
+
+impl<P: fmt::Show + Send + Sync, V: fmt::Show + Send + Sync, C: Compare<P> + Clone> fmt::Show
+        for PriorityQueue<P, V, C> {
+    /// Format the pairs of the queue in the order they will be popped.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "["));
+
+        let mut queue = self.clone();
+        let mut first = true;
+
+        loop {
+            let rest = match queue.pop() {
+                Some((priority, value, rest)) => {
+                    if !first { try!(write!(f, ", ")); }
+                    try!(write!(f, "{}: {}", *priority, *value));
+                    rest
+                },
+                None => break
+            };
+
+            queue = rest;
+            first = false;
+        }
+
+        write!(f, "]")
+    }
+}
+
+impl<P: Send + Sync, V: Send + Sync, C: Compare<P> + Clone + Default> FromIterator<(P, V)>
+        for PriorityQueue<P, V, C> {
+    /// Build a queue from an iterator of pairs.
+    fn from_iter<I: Iterator<(P, V)>>(iter: I) -> PriorityQueue<P, V, C> {
+        let mut queue = PriorityQueue::with_comparator(Default::default());
+        queue.extend(iter);
+        queue
+    }
+}
+
+impl<P: Send + Sync, V: Send + Sync, C: Compare<P> + Clone> Extend<(P, V)>
+        for PriorityQueue<P, V, C> {
+    /// Add pairs from an iterator to the queue.
+    fn extend<I: Iterator<(P, V)>>(&mut self, iter: I) {
+        for (priority, value) in iter {
+            *self = self.push(priority, value);
+        }
+    }
+}
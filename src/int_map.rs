@@ -0,0 +1,646 @@
+use std::sync::Arc;
+use std::default::Default;
+use std::fmt;
+use std::iter::FromIterator;
+
+pub use self::IntMap::{Bin, Tip, Nil};
+
+/// A key value store with integer keys, implemented as a persistent,
+/// functional big-endian Patricia tree.
+///
+/// Each branch tests a single bit of the key, and branches test bits from
+/// the highest downwards, so the pairs are ordered by key and lookups walk
+/// at most one branch for every bit of a key. Set operations only descend
+/// into the parts of the trees whose prefixes overlap, sharing the rest.
+pub enum IntMap<V> {
+    /// A branch node.
+    Bin {
+        /// The bits of every key in this branch above the branching bit.
+        prefix: u64,
+
+        /// The branching bit. Keys in the left branch have it clear, and keys
+        /// in the right branch have it set.
+        mask: u64,
+
+        /// The size of this branch.
+        size: uint,
+
+        /// The branch with the branching bit clear.
+        left: Arc<IntMap<V>>,
+
+        /// The branch with the branching bit set.
+        right: Arc<IntMap<V>>
+    },
+
+    /// A leaf holding one pair.
+    Tip {
+        /// The key of this pair.
+        key: u64,
+
+        /// The value of this pair.
+        value: Arc<V>
+    },
+
+    /// The empty map. Branches never have an empty child.
+    Nil
+}
+
+impl<V: Send + Sync> Clone for IntMap<V> {
+    fn clone(&self) -> IntMap<V> {
+        match *self {
+            Bin { prefix, mask, size, ref left, ref right } => Bin {
+                prefix: prefix,
+                mask: mask,
+                size: size,
+                left: left.clone(),
+                right: right.clone()
+            },
+            Tip { key, ref value } => Tip { key: key, value: value.clone() },
+            Nil => Nil
+        }
+    }
+}
+
+impl<V: Send + Sync> Default for IntMap<V> {
+    #[inline]
+    fn default() -> IntMap<V> { IntMap::new() }
+}
+
+// Is the bit selected by `mask` clear in `key`?
+#[inline]
+fn zero(key: u64, mask: u64) -> bool { key & mask == 0 }
+
+// The bits of `key` above the bit selected by `mask`.
+#[inline]
+fn mask_bits(key: u64, mask: u64) -> u64 { key & !(mask | (mask - 1)) }
+
+// Does `key` lie outside a branch with this prefix and mask?
+#[inline]
+fn no_match(key: u64, prefix: u64, mask: u64) -> bool { mask_bits(key, mask) != prefix }
+
+// Is a branch with mask `a` above a branch with mask `b`? Higher branches
+// test higher bits.
+#[inline]
+fn shorter(a: u64, b: u64) -> bool { a > b }
+
+// The highest bit at which two prefixes differ.
+#[inline]
+fn branching_bit(a: u64, b: u64) -> u64 { 1 << (63 - (a ^ b).leading_zeros()) }
+
+// Join two trees whose prefixes differ under a new branch.
+fn link<V: Send + Sync>(p1: u64, t1: Arc<IntMap<V>>, p2: u64, t2: Arc<IntMap<V>>) -> IntMap<V> {
+    let mask = branching_bit(p1, p2);
+    let prefix = mask_bits(p1, mask);
+
+    if zero(p1, mask) {
+        IntMap::bin(prefix, mask, t1, t2)
+    } else {
+        IntMap::bin(prefix, mask, t2, t1)
+    }
+}
+
+// Constructors
+impl<V: Send + Sync> IntMap<V> {
+    /// An empty map.
+    #[inline]
+    pub fn new() -> IntMap<V> { Nil }
+
+    /// Create a map with one key value pair.
+    #[inline]
+    pub fn singleton(key: u64, value: V) -> IntMap<V> { Tip { key: key, value: Arc::new(value) } }
+
+    // Bin constructor which takes care of size.
+    fn bin(prefix: u64, mask: u64, left: Arc<IntMap<V>>, right: Arc<IntMap<V>>) -> IntMap<V> {
+        Bin {
+            prefix: prefix,
+            mask: mask,
+            size: left.len() + right.len(),
+            left: left,
+            right: right
+        }
+    }
+
+    // Bin constructor for when either branch may have become empty, in
+    // which case the other branch takes the place of the whole node.
+    fn bin_check(prefix: u64, mask: u64, left: Arc<IntMap<V>>, right: Arc<IntMap<V>>) -> IntMap<V> {
+        match (&*left, &*right) {
+            (&Nil, r) => r.clone(),
+            (l, &Nil) => l.clone(),
+            _ => IntMap::bin(prefix, mask, left.clone(), right.clone())
+        }
+    }
+}
+
+// Lookup
+impl<V: Send + Sync> IntMap<V> {
+    /// How many pairs are in the map.
+    #[inline]
+    pub fn len(&self) -> uint {
+        match *self {
+            Bin { size, .. } => size,
+            Tip { .. } => 1,
+            Nil => 0
+        }
+    }
+
+    /// Is the map empty?
+    #[inline]
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+
+    /// Get the value at a key in the map.
+    #[inline]
+    pub fn get(&self, key: u64) -> Option<&V> { self.get_arc(key).map(|value| &**value) }
+
+    /// Is this key a member of the map?
+    #[inline]
+    pub fn contains(&self, key: u64) -> bool { self.get_arc(key).is_some() }
+
+    fn get_arc(&self, key: u64) -> Option<&Arc<V>> {
+        let mut node = self;
+
+        loop {
+            match *node {
+                Nil => return None,
+                Tip { key: k, ref value } => return if k == key { Some(value) } else { None },
+                Bin { prefix, mask, ref left, ref right, .. } => {
+                    if no_match(key, prefix, mask) { return None }
+                    node = if zero(key, mask) { &**left } else { &**right };
+                }
+            }
+        }
+    }
+
+    /// Get an iterator over the pairs of the map, in ascending order of keys.
+    pub fn iter<'a>(&'a self) -> IntMapItems<'a, V> {
+        IntMapItems {
+            stack: vec![self],
+            remaining: self.len()
+        }
+    }
+}
+
+// Insertion and deletion
+impl<V: Send + Sync> IntMap<V> {
+    /// Insert a key value pair into the map. If the key is already present,
+    /// its value is replaced.
+    pub fn insert(&self, key: u64, value: Arc<V>) -> IntMap<V> {
+        self.insert_ref(key, &mut |_| value.clone())
+    }
+
+    // Insert at a key, with the value the closure gives for the value
+    // already at that key, if any.
+    fn insert_ref(&self, key: u64, f: &mut |Option<&Arc<V>>| -> Arc<V>) -> IntMap<V> {
+        match *self {
+            Nil => Tip { key: key, value: (*f)(None) },
+            Tip { key: k, ref value } => {
+                if k == key {
+                    Tip { key: key, value: (*f)(Some(value)) }
+                } else {
+                    link(key, Arc::new(Tip { key: key, value: (*f)(None) }), k, Arc::new(self.clone()))
+                }
+            },
+            Bin { prefix, mask, ref left, ref right, .. } => {
+                if no_match(key, prefix, mask) {
+                    let tip = Tip { key: key, value: (*f)(None) };
+                    link(key, Arc::new(tip), prefix, Arc::new(self.clone()))
+                } else if zero(key, mask) {
+                    IntMap::bin(prefix, mask, Arc::new(left.insert_ref(key, f)), right.clone())
+                } else {
+                    IntMap::bin(prefix, mask, left.clone(), Arc::new(right.insert_ref(key, f)))
+                }
+            }
+        }
+    }
+
+    /// Delete a key and its value from the map.
+    ///
+    /// If the key is not a member of the map, the original map is returned.
+    pub fn delete(&self, key: u64) -> IntMap<V> {
+        match *self {
+            Nil => Nil,
+            Tip { key: k, .. } => if k == key { Nil } else { self.clone() },
+            Bin { prefix, mask, ref left, ref right, .. } => {
+                if no_match(key, prefix, mask) {
+                    self.clone()
+                } else if zero(key, mask) {
+                    IntMap::bin_check(prefix, mask, Arc::new(left.delete(key)), right.clone())
+                } else {
+                    IntMap::bin_check(prefix, mask, left.clone(), Arc::new(right.delete(key)))
+                }
+            }
+        }
+    }
+}
+
+// Updates
+impl<V: Send + Sync> IntMap<V> {
+    /// Modify the value at a key in the map.
+    ///
+    /// If the key is not a member of the map, the original map is returned.
+    pub fn adjust(&self, key: u64, modifier: |&V| -> V) -> IntMap<V> {
+        match self.get(key) {
+            Some(value) => self.insert(key, Arc::new(modifier(value))),
+            None => self.clone()
+        }
+    }
+
+    /// Modify or delete the value at a key in the map.
+    ///
+    /// If the closure returns None, the key is deleted. If the key is not a
+    /// member of the map, the original map is returned.
+    pub fn update(&self, key: u64, modifier: |&V| -> Option<V>) -> IntMap<V> {
+        match self.get(key) {
+            Some(value) => match modifier(value) {
+                Some(value) => self.insert(key, Arc::new(value)),
+                None => self.delete(key)
+            },
+            None => self.clone()
+        }
+    }
+
+    /// Insert, modify or delete the value at a key in the map.
+    ///
+    /// The closure is called with the value at the key if there is one, and
+    /// returns the new value, or None to delete the key or leave it not
+    /// inserted.
+    pub fn alter(&self, key: u64, modifier: |Option<&V>| -> Option<V>) -> IntMap<V> {
+        match modifier(self.get(key)) {
+            Some(value) => self.insert(key, Arc::new(value)),
+            None => self.delete(key)
+        }
+    }
+}
+
+// Set operations
+//
+// These walk both trees together, comparing the prefixes of their branches.
+// A branch whose prefix does not overlap the other tree at all is kept or
+// dropped whole, without visiting any of its pairs.
+impl<V: Send + Sync> IntMap<V> {
+    /// The union of two maps.
+    ///
+    /// If a key is present in both maps, the value from `self` is used.
+    pub fn union(&self, other: &IntMap<V>) -> IntMap<V> {
+        self.merge_with(other, &mut |_, ours, _| ours.clone())
+    }
+
+    /// The union of two maps, combining the values of keys present in both
+    /// maps with the provided closure.
+    ///
+    /// The closure is called with the key, the value from `self` and the
+    /// value from `other`.
+    pub fn union_with(&self, other: &IntMap<V>, combine: |u64, &V, &V| -> V) -> IntMap<V> {
+        self.merge_with(other, &mut |key, ours, theirs| Arc::new(combine(key, &**ours, &**theirs)))
+    }
+
+    fn merge_with(&self, other: &IntMap<V>, f: &mut |u64, &Arc<V>, &Arc<V>| -> Arc<V>) -> IntMap<V> {
+        match (self, other) {
+            (&Nil, _) => other.clone(),
+            (_, &Nil) => self.clone(),
+            (&Tip { key, ref value }, _) => other.insert_ref(key, &mut |found| {
+                match found {
+                    Some(theirs) => (*f)(key, value, theirs),
+                    None => value.clone()
+                }
+            }),
+            (_, &Tip { key, ref value }) => self.insert_ref(key, &mut |found| {
+                match found {
+                    Some(ours) => (*f)(key, ours, value),
+                    None => value.clone()
+                }
+            }),
+            (&Bin { prefix: p1, mask: m1, left: ref l1, right: ref r1, .. },
+             &Bin { prefix: p2, mask: m2, left: ref l2, right: ref r2, .. }) => {
+                if shorter(m1, m2) {
+                    if no_match(p2, p1, m1) {
+                        link(p1, Arc::new(self.clone()), p2, Arc::new(other.clone()))
+                    } else if zero(p2, m1) {
+                        IntMap::bin(p1, m1, Arc::new(l1.merge_with(other, f)), r1.clone())
+                    } else {
+                        IntMap::bin(p1, m1, l1.clone(), Arc::new(r1.merge_with(other, f)))
+                    }
+                } else if shorter(m2, m1) {
+                    if no_match(p1, p2, m2) {
+                        link(p1, Arc::new(self.clone()), p2, Arc::new(other.clone()))
+                    } else if zero(p1, m2) {
+                        IntMap::bin(p2, m2, Arc::new(self.merge_with(&**l2, f)), r2.clone())
+                    } else {
+                        IntMap::bin(p2, m2, l2.clone(), Arc::new(self.merge_with(&**r2, f)))
+                    }
+                } else if p1 == p2 {
+                    IntMap::bin(p1, m1, Arc::new(l1.merge_with(&**l2, f)),
+                                Arc::new(r1.merge_with(&**r2, f)))
+                } else {
+                    link(p1, Arc::new(self.clone()), p2, Arc::new(other.clone()))
+                }
+            }
+        }
+    }
+
+    /// The intersection of two maps.
+    ///
+    /// The values from `self` are used.
+    pub fn intersection<W: Send + Sync>(&self, other: &IntMap<W>) -> IntMap<V> {
+        self.intersection_ref(other, &mut |_, ours, _| ours.clone())
+    }
+
+    /// The intersection of two maps, combining the values of each key with
+    /// the provided closure.
+    ///
+    /// The closure is called with the key, the value from `self` and the
+    /// value from `other`.
+    pub fn intersection_with<W: Send + Sync, X: Send + Sync>(&self, other: &IntMap<W>,
+                                                             combine: |u64, &V, &W| -> X) -> IntMap<X> {
+        self.intersection_ref(other, &mut |key, ours, theirs| Arc::new(combine(key, &**ours, &**theirs)))
+    }
+
+    fn intersection_ref<W: Send + Sync, X: Send + Sync>(&self, other: &IntMap<W>,
+                                                        f: &mut |u64, &Arc<V>, &Arc<W>| -> Arc<X>)
+            -> IntMap<X> {
+        match (self, other) {
+            (&Nil, _) | (_, &Nil) => Nil,
+            (&Tip { key, ref value }, _) => match other.get_arc(key) {
+                Some(theirs) => Tip { key: key, value: (*f)(key, value, theirs) },
+                None => Nil
+            },
+            (_, &Tip { key, ref value }) => match self.get_arc(key) {
+                Some(ours) => Tip { key: key, value: (*f)(key, ours, value) },
+                None => Nil
+            },
+            (&Bin { prefix: p1, mask: m1, left: ref l1, right: ref r1, .. },
+             &Bin { prefix: p2, mask: m2, left: ref l2, right: ref r2, .. }) => {
+                if shorter(m1, m2) {
+                    if no_match(p2, p1, m1) {
+                        Nil
+                    } else if zero(p2, m1) {
+                        l1.intersection_ref(other, f)
+                    } else {
+                        r1.intersection_ref(other, f)
+                    }
+                } else if shorter(m2, m1) {
+                    if no_match(p1, p2, m2) {
+                        Nil
+                    } else if zero(p1, m2) {
+                        self.intersection_ref(&**l2, f)
+                    } else {
+                        self.intersection_ref(&**r2, f)
+                    }
+                } else if p1 == p2 {
+                    IntMap::bin_check(p1, m1, Arc::new(l1.intersection_ref(&**l2, f)),
+                                      Arc::new(r1.intersection_ref(&**r2, f)))
+                } else {
+                    Nil
+                }
+            }
+        }
+    }
+
+    /// The pairs of `self` whose keys are not in `other`.
+    pub fn difference<W: Send + Sync>(&self, other: &IntMap<W>) -> IntMap<V> {
+        match (self, other) {
+            (&Nil, _) => Nil,
+            (_, &Nil) => self.clone(),
+            (&Tip { key, .. }, _) => if other.contains(key) { Nil } else { self.clone() },
+            (_, &Tip { key, .. }) => self.delete(key),
+            (&Bin { prefix: p1, mask: m1, left: ref l1, right: ref r1, .. },
+             &Bin { prefix: p2, mask: m2, left: ref l2, right: ref r2, .. }) => {
+                if shorter(m1, m2) {
+                    if no_match(p2, p1, m1) {
+                        self.clone()
+                    } else if zero(p2, m1) {
+                        IntMap::bin_check(p1, m1, Arc::new(l1.difference(other)), r1.clone())
+                    } else {
+                        IntMap::bin_check(p1, m1, l1.clone(), Arc::new(r1.difference(other)))
+                    }
+                } else if shorter(m2, m1) {
+                    if no_match(p1, p2, m2) {
+                        self.clone()
+                    } else if zero(p1, m2) {
+                        self.difference(&**l2)
+                    } else {
+                        self.difference(&**r2)
+                    }
+                } else if p1 == p2 {
+                    IntMap::bin_check(p1, m1, Arc::new(l1.difference(&**l2)),
+                                      Arc::new(r1.difference(&**r2)))
+                } else {
+                    self.clone()
+                }
+            }
+        }
+    }
+}
+
+// Comparisons
+//
+// A Patricia tree has only one shape for a given set of keys, but maps are
+// compared by their pairs in order, like Map.
+impl<V: PartialEq + Send + Sync> PartialEq for IntMap<V> {
+    fn eq(&self, other: &IntMap<V>) -> bool {
+        self.len() == other.len() && self.iter().zip(other.iter()).all(|(a, b)| a == b)
+    }
+}
+
+impl<V: Eq + Send + Sync> Eq for IntMap<V> {}
+
+impl<V: fmt::Show + Send + Sync> fmt::Show for IntMap<V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{{"));
+
+        for (i, (key, value)) in self.iter().enumerate() {
+            if i != 0 { try!(write!(f, ", ")); }
+            try!(write!(f, "{}: {}", key, *value));
+        }
+
+        write!(f, "}}")
+    }
+}
+
+impl<V: Send + Sync> FromIterator<(u64, V)> for IntMap<V> {
+    /// Build a map from an iterator of pairs. Later values replace earlier
+    /// ones.
+    fn from_iter<I: Iterator<(u64, V)>>(iter: I) -> IntMap<V> {
+        let mut map = IntMap::new();
+        map.extend(iter);
+        map
+    }
+}
+
+impl<V: Send + Sync> Extend<(u64, V)> for IntMap<V> {
+    /// Add pairs from an iterator to the map, replacing the values of keys
+    /// which are already present.
+    fn extend<I: Iterator<(u64, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            *self = self.insert(key, Arc::new(value));
+        }
+    }
+}
+
+/// An iterator over the pairs of an integer map, in ascending order of keys.
+pub struct IntMapItems<'a, V: 'a> {
+    // Subtrees which have yet to be visited, the next one on top.
+    stack: Vec<&'a IntMap<V>>,
+
+    remaining: uint
+}
+
+impl<'a, V: Send + Sync> Iterator<(u64, &'a V)> for IntMapItems<'a, V> {
+    fn next(&mut self) -> Option<(u64, &'a V)> {
+        loop {
+            match self.stack.pop() {
+                None => return None,
+                Some(&Nil) => {},
+                Some(&Tip { key, ref value }) => {
+                    self.remaining -= 1;
+                    return Some((key, &**value))
+                },
+                Some(&Bin { ref left, ref right, .. }) => {
+                    self.stack.push(&**right);
+                    self.stack.push(&**left);
+                }
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (uint, Option<uint>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+/// A persistent, functional set of integers, implemented as a big-endian
+/// Patricia tree.
+///
+/// Like `Set`, every key shares a single `Arc` for its value.
+pub struct IntSet {
+    map: IntMap<()>
+}
+
+impl IntSet {
+    /// An empty set.
+    #[inline]
+    pub fn new() -> IntSet { IntSet { map: IntMap::new() } }
+
+    /// Create a set with one key.
+    #[inline]
+    pub fn singleton(key: u64) -> IntSet { IntSet { map: IntMap::singleton(key, ()) } }
+
+    /// How many keys are in the set.
+    #[inline]
+    pub fn len(&self) -> uint { self.map.len() }
+
+    /// Is the set empty?
+    #[inline]
+    pub fn is_empty(&self) -> bool { self.map.is_empty() }
+
+    /// Is this key a member of the set?
+    #[inline]
+    pub fn contains(&self, key: u64) -> bool { self.map.contains(key) }
+
+    /// Insert a key into the set.
+    pub fn insert(&self, key: u64) -> IntSet { IntSet { map: self.map.insert(key, self.unit()) } }
+
+    /// Delete a key from the set.
+    ///
+    /// If the key is not a member of the set, the original set is returned.
+    #[inline]
+    pub fn delete(&self, key: u64) -> IntSet { IntSet { map: self.map.delete(key) } }
+
+    /// The keys which are in either set.
+    #[inline]
+    pub fn union(&self, other: &IntSet) -> IntSet { IntSet { map: self.map.union(&other.map) } }
+
+    /// The keys which are in both sets.
+    #[inline]
+    pub fn intersection(&self, other: &IntSet) -> IntSet {
+        IntSet { map: self.map.intersection(&other.map) }
+    }
+
+    /// The keys of `self` which are not in `other`.
+    #[inline]
+    pub fn difference(&self, other: &IntSet) -> IntSet {
+        IntSet { map: self.map.difference(&other.map) }
+    }
+
+    /// Get an iterator over the keys of the set, in ascending order.
+    #[inline]
+    pub fn iter<'a>(&'a self) -> IntSetItems<'a> { IntSetItems { iter: self.map.iter() } }
+
+    /// Get the map backing this set.
+    #[inline]
+    pub fn as_map(&self) -> &IntMap<()> { &self.map }
+
+    // Like `Set`, every tip shares one unit value. Any tip will do, and the
+    // leftmost is found without comparing keys.
+    fn unit(&self) -> Arc<()> {
+        let mut node = &self.map;
+
+        loop {
+            match *node {
+                Nil => return Arc::new(()),
+                Tip { ref value, .. } => return value.clone(),
+                Bin { ref left, .. } => node = &**left
+            }
+        }
+    }
+}
+
+impl Clone for IntSet {
+    #[inline]
+    fn clone(&self) -> IntSet { IntSet { map: self.map.clone() } }
+}
+
+impl Default for IntSet {
+    #[inline]
+    fn default() -> IntSet { IntSet::new() }
+}
+
+impl PartialEq for IntSet {
+    #[inline]
+    fn eq(&self, other: &IntSet) -> bool { self.map == other.map }
+}
+
+impl Eq for IntSet {}
+
+impl fmt::Show for IntSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{{"));
+
+        for (i, key) in self.iter().enumerate() {
+            if i != 0 { try!(write!(f, ", ")); }
+            try!(write!(f, "{}", key));
+        }
+
+        write!(f, "}}")
+    }
+}
+
+impl FromIterator<u64> for IntSet {
+    /// Build a set from an iterator of keys.
+    fn from_iter<I: Iterator<u64>>(iter: I) -> IntSet {
+        let mut set = IntSet::new();
+        set.extend(iter);
+        set
+    }
+}
+
+impl Extend<u64> for IntSet {
+    /// Add the keys of an iterator to the set.
+    fn extend<I: Iterator<u64>>(&mut self, iter: I) {
+        for key in iter {
+            *self = self.insert(key);
+        }
+    }
+}
+
+/// An iterator over the keys of an integer set, in ascending order.
+pub struct IntSetItems<'a> {
+    iter: IntMapItems<'a, ()>
+}
+
+impl<'a> Iterator<u64> for IntSetItems<'a> {
+    #[inline]
+    fn next(&mut self) -> Option<u64> { self.iter.next().map(|(key, _)| key) }
+
+    #[inline]
+    fn size_hint(&self) -> (uint, Option<uint>) { self.iter.size_hint() }
+}
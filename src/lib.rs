@@ -2,16 +2,37 @@
 #![deny(missing_docs)]
 #![deny(warnings)]
 
-#![feature(macro_rules)]
+#![feature(macro_rules, unsafe_destructor, default_type_params)]
 
 //! Persistent, immutable, functional data structures in Rust.
 
-pub use self::list::List;
+extern crate alloc;
+
+pub use self::hash_map::HashMap;
+pub use self::heap::{Heap, PriorityQueue};
+pub use self::int_map::{IntMap, IntSet};
+pub use self::list::{List, Stack};
 pub use self::map::Map;
+pub use self::set::Set;
+pub use self::trie::Trie;
+
+/// Contains the hash map type.
+pub mod hash_map;
+
+/// Contains the heap and priority queue types.
+pub mod heap;
 
-/// Contains the list type.
+/// Contains the integer map and integer set types.
+pub mod int_map;
+
+/// Contains the list and stack types.
 pub mod list;
 
 /// Contains the map type.
 pub mod map;
 
+/// Contains the set type.
+pub mod set;
+
+/// Contains the trie type.
+pub mod trie;
@@ -1,4 +1,10 @@
 use std::sync::Arc;
+use std::default::Default;
+use std::fmt;
+use std::hash::{Hash, Writer};
+use std::iter::FromIterator;
+use std::mem;
+use alloc::arc;
 use self::List::{Cons, Nil};
 
 /// A functional, shareable, persistent singly linked list.
@@ -43,6 +49,294 @@ impl<T: Send + Sync> List<T> {
             list: self
         }
     }
+
+    /// Create a new list with an item in front of this one, which becomes
+    /// its tail.
+    ///
+    /// This takes the list by value; use `SharedList::cons` to put an item in
+    /// front of a list which is shared.
+    #[inline]
+    pub fn push_front(self, val: T) -> List<T> { Cons(val, Arc::new(self)) }
+
+    /// Get the head and tail of a list.
+    ///
+    /// Returns None if the list is empty.
+    pub fn pop_front(&self) -> Option<(&T, Arc<List<T>>)> {
+        match *self {
+            Nil => None,
+            Cons(ref head, ref tail) => Some((head, tail.clone()))
+        }
+    }
+
+    /// How many items are in the list.
+    ///
+    /// This walks the whole list; use a `Stack` to keep track of the length.
+    pub fn len(&self) -> uint { self.iter().count() }
+
+    /// Is the list empty?
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        match *self {
+            Nil => true,
+            Cons(..) => false
+        }
+    }
+}
+
+/// Operations on a list which is already shared.
+///
+/// The tail of a list is an `Arc`, so a list can only become the tail of
+/// another one without being copied if it is behind an `Arc` itself.
+pub trait SharedList<T> {
+    /// Create a new list with an item in front of this one, which becomes
+    /// its tail and is shared rather than copied.
+    fn cons(&self, val: T) -> List<T>;
+}
+
+impl<T: Send + Sync> SharedList<T> for Arc<List<T>> {
+    #[inline]
+    fn cons(&self, val: T) -> List<T> { Cons(val, self.clone()) }
+}
+
+/// A persistent stack, built on a list.
+///
+/// Pushing and popping share the rest of the stack, and unlike a bare list a
+/// stack knows its length.
+pub struct Stack<T> {
+    list: Arc<List<T>>,
+    len: uint
+}
+
+impl<T: Send + Sync> Stack<T> {
+    /// Construct a new, empty stack.
+    #[inline]
+    pub fn new() -> Stack<T> {
+        Stack {
+            list: Arc::new(Nil),
+            len: 0
+        }
+    }
+
+    /// How many items are on the stack.
+    #[inline]
+    pub fn len(&self) -> uint { self.len }
+
+    /// Is the stack empty?
+    #[inline]
+    pub fn is_empty(&self) -> bool { self.len == 0 }
+
+    /// Get the item on top of the stack.
+    #[inline]
+    pub fn peek(&self) -> Option<&T> { self.list.head() }
+
+    /// Create a new stack with an item pushed on top of this one.
+    pub fn push(&self, val: T) -> Stack<T> {
+        Stack {
+            list: Arc::new(Cons(val, self.list.clone())),
+            len: self.len + 1
+        }
+    }
+
+    /// Get the item on top of the stack, and the stack below it.
+    ///
+    /// Returns None if the stack is empty.
+    pub fn pop(&self) -> Option<(&T, Stack<T>)> {
+        match *self.list {
+            Nil => None,
+            Cons(ref head, ref tail) => Some((head, Stack {
+                list: tail.clone(),
+                len: self.len - 1
+            }))
+        }
+    }
+
+    /// Get an iterator over the items on the stack, from the top down.
+    #[inline]
+    pub fn iter<'a>(&'a self) -> ListItems<'a, T> { self.list.iter() }
+
+    /// Get the list backing this stack.
+    #[inline]
+    pub fn as_list(&self) -> &List<T> { &*self.list }
+}
+
+impl<T: Send + Sync> Clone for Stack<T> {
+    fn clone(&self) -> Stack<T> {
+        Stack {
+            list: self.list.clone(),
+            len: self.len
+        }
+    }
+}
+
+impl<T: Send + Sync> Default for Stack<T> {
+    #[inline]
+    fn default() -> Stack<T> { Stack::new() }
+}
+
+// The drop glue for a list drops its tail, which drops the tail's tail and
+// so on, recursing once for every cell. Long lists overflow the stack that
+// way, so cells are unlinked from their tails and dropped one at a time.
+#[unsafe_destructor]
+impl<T: Send + Sync> Drop for List<T> {
+    fn drop(&mut self) {
+        let (mut next, nil) = match *self {
+            Cons(_, ref mut tail) => {
+                // A tail which is empty or shared has nothing to unlink, which
+                // is always the case for the cells unlinked below.
+                if tail.is_empty() || arc::get_mut(tail).is_none() { return }
+
+                let nil = Arc::new(Nil);
+                (mem::replace(tail, nil.clone()), nil)
+            },
+            Nil => return
+        };
+
+        // A cell can only be unlinked while this is the only reference to
+        // it. The first shared tail is left alone, since some other list
+        // still uses it and will drop it later.
+        loop {
+            let tail = match arc::get_mut(&mut next) {
+                Some(&mut Cons(_, ref mut tail)) => mem::replace(tail, nil.clone()),
+                _ => break
+            };
+
+            // Drops the unlinked cell, whose tail is now the shared Nil.
+            next = tail;
+        }
+    }
+}
+
+impl<T: Clone + Send + Sync> Clone for List<T> {
+    /// Clone the head of the list, sharing its tail.
+    fn clone(&self) -> List<T> {
+        match *self {
+            Cons(ref head, ref tail) => Cons(head.clone(), tail.clone()),
+            Nil => Nil
+        }
+    }
+}
+
+impl<T> Default for List<T> {
+    #[inline]
+    fn default() -> List<T> { List::new() }
+}
+
+// Comparisons
+//
+// Lists are compared item by item, but once both lists reach the same shared
+// tail the rest of them must be equal, so it is not compared.
+impl<T: PartialEq + Send + Sync> PartialEq for List<T> {
+    fn eq(&self, other: &List<T>) -> bool {
+        let mut a = self;
+        let mut b = other;
+
+        loop {
+            if a as *const List<T> == b as *const List<T> { return true }
+
+            match (a, b) {
+                (&Cons(ref x, ref xs), &Cons(ref y, ref ys)) => {
+                    if x != y { return false }
+                    a = &**xs;
+                    b = &**ys;
+                },
+                (&Nil, &Nil) => return true,
+                _ => return false
+            }
+        }
+    }
+}
+
+impl<T: Eq + Send + Sync> Eq for List<T> {}
+
+impl<T: PartialOrd + Send + Sync> PartialOrd for List<T> {
+    fn partial_cmp(&self, other: &List<T>) -> Option<Ordering> {
+        let mut a = self;
+        let mut b = other;
+
+        loop {
+            if a as *const List<T> == b as *const List<T> { return Some(Equal) }
+
+            match (a, b) {
+                (&Cons(ref x, ref xs), &Cons(ref y, ref ys)) => match x.partial_cmp(y) {
+                    Some(Equal) => {
+                        a = &**xs;
+                        b = &**ys;
+                    },
+                    order => return order
+                },
+                (&Nil, &Nil) => return Some(Equal),
+                (&Nil, _) => return Some(Less),
+                (_, &Nil) => return Some(Greater)
+            }
+        }
+    }
+}
+
+impl<T: Ord + Send + Sync> Ord for List<T> {
+    fn cmp(&self, other: &List<T>) -> Ordering {
+        let mut a = self;
+        let mut b = other;
+
+        loop {
+            if a as *const List<T> == b as *const List<T> { return Equal }
+
+            match (a, b) {
+                (&Cons(ref x, ref xs), &Cons(ref y, ref ys)) => match x.cmp(y) {
+                    Equal => {
+                        a = &**xs;
+                        b = &**ys;
+                    },
+                    order => return order
+                },
+                (&Nil, &Nil) => return Equal,
+                (&Nil, _) => return Less,
+                (_, &Nil) => return Greater
+            }
+        }
+    }
+}
+
+impl<S: Writer, T: Hash<S> + Send + Sync> Hash<S> for List<T> {
+    fn hash(&self, state: &mut S) {
+        let mut len = 0u;
+        for item in self.iter() {
+            item.hash(state);
+            len += 1;
+        }
+        len.hash(state);
+    }
+}
+
+impl<T: fmt::Show + Send + Sync> fmt::Show for List<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "["));
+
+        for (i, item) in self.iter().enumerate() {
+            if i != 0 { try!(write!(f, ", ")); }
+            try!(write!(f, "{}", *item));
+        }
+
+        write!(f, "]")
+    }
+}
+
+impl<T: Send + Sync> FromIterator<T> for List<T> {
+    /// Build a list with the items of an iterator, in the same order.
+    fn from_iter<I: Iterator<T>>(iter: I) -> List<T> {
+        let items: Vec<T> = iter.collect();
+        items.into_iter().rev().fold(Nil, |tail, head| Cons(head, Arc::new(tail)))
+    }
+}
+
+impl<T: Clone + Send + Sync> Extend<T> for List<T> {
+    /// Add the items of an iterator to the end of the list.
+    ///
+    /// Since the end of the list changes, none of it can be shared and every
+    /// item already in the list is cloned.
+    fn extend<I: Iterator<T>>(&mut self, iter: I) {
+        let items: Vec<T> = self.iter().map(|item| item.clone()).chain(iter).collect();
+        *self = items.into_iter().collect();
+    }
 }
 
 /// An iterator over the items in a list.
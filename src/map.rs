@@ -1,7 +1,26 @@
 use std::sync::Arc;
 use std::default::Default;
+use std::borrow::BorrowFrom;
+use std::collections::RingBuf;
+use std::error::Error;
+use std::fmt;
+use std::hash::{Hash, Writer};
+use std::iter::{ExactSize, FromIterator};
 
 pub use self::Map::{Bin, Tip};
+pub use self::Bound::{Included, Excluded, Unbounded};
+pub use self::Entry::{Occupied, Vacant};
+use self::Side::{Left, Right};
+
+// Unlike a list, a map does not need a custom Drop to be freed safely. The
+// drop glue recurses once for every level of the tree, and every operation
+// which changes its shape goes through balance, glue, link or merge, so the
+// depth stays logarithmic in the size of the map.
+//
+// That only holds for balanced maps. The `Bin` variant and `Map::bin` are
+// public and can build chains of any depth, and a map built that way can
+// overflow the stack when it is dropped, just as its lookups can take
+// linear time. `validate` reports such maps as unbalanced.
 
 /// A key value store, implemented as a persistent, functional
 /// size balanced binary search tree.
@@ -28,6 +47,18 @@ pub enum Map<K, V> {
     Tip
 }
 
+/// One end of a range of keys.
+pub enum Bound<T> {
+    /// The range includes keys equal to this value.
+    Included(T),
+
+    /// The range does not include keys equal to this value.
+    Excluded(T),
+
+    /// This end of the range is open.
+    Unbounded
+}
+
 impl<K: Send + Sync, V: Send + Sync> Clone for Map<K, V> {
     fn clone(&self) -> Map<K, V> {
         match *self {
@@ -52,9 +83,12 @@ impl<K, V> Map<K, V> {
 
 impl<K: Ord + Send + Sync, V: Send + Sync> Map<K, V> {
     /// Lookup a value in the map.
-    pub fn get<'a>(&'a self, lookup: &K) -> Option<&'a V> {
+    ///
+    /// The key may be any borrowed form of the map's key type, such as a
+    /// `&str` for a `Map<String, V>`, as long as it is ordered the same way.
+    pub fn get<'a, Sized? Q>(&'a self, lookup: &Q) -> Option<&'a V> where Q: Ord + BorrowFrom<K> {
         match *self {
-            Bin { ref key, ref left, ref right, ref value, .. } => match key.deref().cmp(lookup) {
+            Bin { ref key, ref left, ref right, ref value, .. } => match lookup.cmp(BorrowFrom::borrow_from(&**key)) {
                 Equal   => Some(&**value),
                 Less    => left.get(lookup),
                 Greater => right.get(lookup)
@@ -66,17 +100,97 @@ impl<K: Ord + Send + Sync, V: Send + Sync> Map<K, V> {
 
 impl<K: Ord + Send + Sync, V: Send + Sync> Map<K, V> {
     /// Is this key in the map?
-    pub fn contains(&self, lookup: &K) -> bool {
+    pub fn contains<Sized? Q>(&self, lookup: &Q) -> bool where Q: Ord + BorrowFrom<K> {
         self.get(lookup).is_some()
     }
 
-//     fn is_disjoint(&self, other: &Map<K, V>) -> bool {
-//         self.inorder_iter().all(|(k, _)| !other.contains(k.deref()))
-//     }
-//
-//     fn is_subset(&self, other: &Map<K, V>) -> bool {
-//         self.inorder_iter().all(|(k, _)| other.contains(k.deref()))
-//     }
+    /// Find the pair with the largest key strictly smaller than `lookup`.
+    pub fn lookup_lt<'a, Sized? Q>(&'a self, lookup: &Q) -> Option<(&'a K, &'a V)>
+            where Q: Ord + BorrowFrom<K> {
+        let mut node = self;
+        let mut best = None;
+
+        loop {
+            match *node {
+                Tip => return best,
+                Bin { ref key, ref value, ref left, ref right, .. } => {
+                    if lookup.cmp(BorrowFrom::borrow_from(&**key)) == Greater {
+                        best = Some((&**key, &**value));
+                        node = &**right;
+                    } else {
+                        node = &**left;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Find the pair with the smallest key strictly larger than `lookup`.
+    pub fn lookup_gt<'a, Sized? Q>(&'a self, lookup: &Q) -> Option<(&'a K, &'a V)>
+            where Q: Ord + BorrowFrom<K> {
+        let mut node = self;
+        let mut best = None;
+
+        loop {
+            match *node {
+                Tip => return best,
+                Bin { ref key, ref value, ref left, ref right, .. } => {
+                    if lookup.cmp(BorrowFrom::borrow_from(&**key)) == Less {
+                        best = Some((&**key, &**value));
+                        node = &**left;
+                    } else {
+                        node = &**right;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Find the pair with the largest key smaller than or equal to `lookup`.
+    pub fn lookup_le<'a, Sized? Q>(&'a self, lookup: &Q) -> Option<(&'a K, &'a V)>
+            where Q: Ord + BorrowFrom<K> {
+        let mut node = self;
+        let mut best = None;
+
+        loop {
+            match *node {
+                Tip => return best,
+                Bin { ref key, ref value, ref left, ref right, .. } => {
+                    match lookup.cmp(BorrowFrom::borrow_from(&**key)) {
+                        Less    => node = &**left,
+                        Equal   => return Some((&**key, &**value)),
+                        Greater => {
+                            best = Some((&**key, &**value));
+                            node = &**right;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Find the pair with the smallest key larger than or equal to `lookup`.
+    pub fn lookup_ge<'a, Sized? Q>(&'a self, lookup: &Q) -> Option<(&'a K, &'a V)>
+            where Q: Ord + BorrowFrom<K> {
+        let mut node = self;
+        let mut best = None;
+
+        loop {
+            match *node {
+                Tip => return best,
+                Bin { ref key, ref value, ref left, ref right, .. } => {
+                    match lookup.cmp(BorrowFrom::borrow_from(&**key)) {
+                        Less    => {
+                            best = Some((&**key, &**value));
+                            node = &**left;
+                        },
+                        Equal   => return Some((&**key, &**value)),
+                        Greater => node = &**right
+                    }
+                }
+            }
+        }
+    }
 }
 
 // Constructors
@@ -98,6 +212,9 @@ impl<K: Send + Sync, V: Send + Sync> Map<K, V> {
     }
 
     /// Bin constructor which takes care of cloning Arcs and size.
+    ///
+    /// The branches are not rebalanced, so the caller has to keep the map
+    /// balanced. Use `link` to join branches of any size.
     #[inline]
     pub fn bin(key: Arc<K>, value: Arc<V>, left: Arc<Map<K, V>>, right: Arc<Map<K, V>>) -> Map<K, V> {
         Bin {
@@ -140,6 +257,117 @@ impl<K: Send + Sync, V: Send + Sync> Default for Map<K, V> {
     fn default() -> Map<K, V> { Map::new() }
 }
 
+// Bulk construction
+impl<K: Send + Sync + Ord, V: Send + Sync> Map<K, V> {
+    /// Build a map from pairs in ascending order of keys, in linear time.
+    ///
+    /// If a key appears more than once, its last value is used. Panics if the
+    /// keys are not in ascending order; use `collect` for pairs in any order.
+    pub fn from_sorted_iter<I: Iterator<(K, V)>>(iter: I) -> Map<K, V> {
+        let mut iter = iter;
+        let (pairs, rest) = sorted_run(&mut iter);
+        if rest.is_some() { panic!("Map::from_sorted_iter: keys are not in ascending order.") }
+        Map::from_pairs(pairs.as_slice()).checked()
+    }
+
+    /// Build a map from pairs in strictly ascending order of keys, in linear
+    /// time.
+    ///
+    /// This skips the duplicate check done by `from_sorted_iter`. The keys must
+    /// be distinct and in ascending order, otherwise the resulting map is not
+    /// ordered.
+    pub fn from_distinct_ascending<I: Iterator<(K, V)>>(iter: I) -> Map<K, V> {
+        Map::from_distinct_ascending_arcs(iter.map(|(key, value)| (Arc::new(key), Arc::new(value))))
+    }
+
+    /// Build a map from shared pairs in strictly ascending order of keys, in
+    /// linear time.
+    ///
+    /// Like `from_distinct_ascending`, but the pairs are shared with the map
+    /// instead of being moved into new Arcs.
+    pub fn from_distinct_ascending_arcs<I: Iterator<(Arc<K>, Arc<V>)>>(iter: I) -> Map<K, V> {
+        let pairs: Vec<(Arc<K>, Arc<V>)> = iter.collect();
+        Map::from_pairs(pairs.as_slice()).checked()
+    }
+
+    // Build a perfectly balanced map from distinct pairs in ascending order.
+    fn from_pairs(pairs: &[(Arc<K>, Arc<V>)]) -> Map<K, V> {
+        if pairs.is_empty() { return Tip }
+
+        let mid = pairs.len() / 2;
+        let (ref key, ref value) = pairs[mid];
+
+        Bin {
+            size: pairs.len(),
+            key: key.clone(),
+            value: value.clone(),
+            left: Arc::new(Map::from_pairs(pairs.slice_to(mid))),
+            right: Arc::new(Map::from_pairs(pairs.slice_from(mid + 1)))
+        }
+    }
+}
+
+// Collect pairs from an iterator for as long as their keys are ascending,
+// keeping the last value of repeated keys. Returns the collected pairs and
+// the first pair that was out of order, if any.
+fn sorted_run<K: Ord + Send + Sync, V: Send + Sync, I: Iterator<(K, V)>>(iter: &mut I)
+        -> (Vec<(Arc<K>, Arc<V>)>, Option<(K, V)>) {
+    let mut pairs: Vec<(Arc<K>, Arc<V>)> = Vec::new();
+
+    loop {
+        match iter.next() {
+            None => return (pairs, None),
+            Some((key, value)) => {
+                let order = match pairs.last() {
+                    Some(&(ref last, _)) => (**last).cmp(&key),
+                    None => Less
+                };
+
+                match order {
+                    Less    => {},
+                    Equal   => { pairs.pop(); },
+                    Greater => return (pairs, Some((key, value)))
+                }
+
+                pairs.push((Arc::new(key), Arc::new(value)));
+            }
+        }
+    }
+}
+
+impl<K: Send + Sync + Ord, V: Send + Sync> FromIterator<(K, V)> for Map<K, V> {
+    /// Build a map from an iterator of pairs.
+    ///
+    /// The longest sorted prefix of the iterator is built in linear time, and
+    /// any remaining pairs are inserted one at a time. Later values replace
+    /// earlier ones.
+    fn from_iter<I: Iterator<(K, V)>>(iter: I) -> Map<K, V> {
+        let mut iter = iter;
+        let (pairs, rest) = sorted_run(&mut iter);
+        let map = Map::from_pairs(pairs.as_slice());
+
+        match rest {
+            None => map,
+            Some((key, value)) => {
+                let map = map.insert(Arc::new(key), Arc::new(value));
+                iter.fold(map, |map, (key, value)| map.insert(Arc::new(key), Arc::new(value)))
+            }
+        }
+    }
+}
+
+impl<K: Send + Sync + Ord, V: Send + Sync> Extend<(K, V)> for Map<K, V> {
+    /// Add pairs from an iterator to the map, replacing the values of keys
+    /// which are already present.
+    ///
+    /// The new pairs are built into a map of their own and then combined with
+    /// this one using `union`.
+    fn extend<I: Iterator<(K, V)>>(&mut self, iter: I) {
+        let new: Map<K, V> = iter.collect();
+        *self = new.union(self);
+    }
+}
+
 // Insertion
 impl<K: Send + Sync + Ord, V: Send + Sync> Map<K, V> {
     /// Insert a key value pair into the map. If they key is already present in
@@ -163,48 +391,32 @@ impl<K: Send + Sync + Ord, V: Send + Sync> Map<K, V> {
     /// Insert a new key value pair into the map. If the key is already
     /// present the old value is used.
     pub fn insert_no_replace(&self, key: Arc<K>, val: Arc<V>) -> Map<K, V> {
-        match *self {
-            Tip => Map::singleton_arc(key, val),
-            Bin { key: ref keyx, value: ref valuex,
-                  left: ref leftx, right: ref rightx, .. } => {
-                match key.cmp(&*keyx) {
-                    Equal   => self.clone(),
-                    Less    => Map::balance(keyx.clone(), valuex.clone(),
-                                            Arc::new(leftx.insert(key, val)), rightx.clone()),
-                    Greater => Map::balance(keyx.clone(), valuex.clone(),
-                                            leftx.clone(), Arc::new(rightx.insert(key, val))),
-                }
-            }
-        }
+        self.entry(key).or_insert(val)
     }
 
     /// Insert a key value pair into the map, if the key is already present,
     /// modify it's value with the passed in closure.
     pub fn insert_or_modify_with(&self, key: Arc<K>, val: Arc<V>, modifier: |&V| -> V) -> Map<K, V> {
-        match *self {
-            Tip => Map::singleton_arc(key, val),
-            Bin { key: ref keyx, value: ref valuex,
-                  left: ref leftx, right: ref rightx, .. } => {
-                match key.cmp(&*keyx) {
-                    Equal   => Map::bin_ref(&key, &Arc::new(modifier(&**valuex)), leftx, rightx),
-                    Less    => Map::balance(keyx.clone(), valuex.clone(),
-                                            Arc::new(leftx.insert(key, val)), rightx.clone()),
-                    Greater => Map::balance(keyx.clone(), valuex.clone(),
-                                            leftx.clone(), Arc::new(rightx.insert(key, val))),
-                }
-            }
-        }
+        self.entry(key).and_modify(modifier).or_insert(val)
     }
 }
 
 static RATIO: uint = 2;
 static DELTA: uint = 3;
 
+// Are two branches with these sizes balanced with respect to each other?
+//
+// This is the condition that `balance` restores.
+#[inline]
+fn is_balanced(left: uint, right: uint) -> bool {
+    left + right <= 1 || (right <= DELTA * left && left <= DELTA * right)
+}
+
 // Balancing
 impl<K: Send + Sync + Ord, V: Send + Sync> Map<K, V> {
     // Create a balanced tree from its constituent parts.
     fn balance(key: Arc<K>, value: Arc<V>, left: Arc<Map<K, V>>, right: Arc<Map<K, V>>) -> Map<K, V> {
-        if left.len() + right.len() <= 1 {
+        let balanced = if left.len() + right.len() <= 1 {
             Map::bin(key, value, left, right)
         } else if right.len() > DELTA * left.len() {
             Map::rotate_left(key, value, left, right)
@@ -212,7 +424,9 @@ impl<K: Send + Sync + Ord, V: Send + Sync> Map<K, V> {
             Map::rotate_right(key, value, left, right)
         } else {
             Map::bin(key, value, left, right)
-        }
+        };
+
+        balanced.checked()
     }
 
     fn rotate_left(key: Arc<K>, value: Arc<V>, left: Arc<Map<K, V>>, right: Arc<Map<K, V>>) -> Map<K, V> {
@@ -259,9 +473,7 @@ impl<K: Send + Sync + Ord, V: Send + Sync> Map<K, V> {
         }
     }
 
-    // FIXME: Something is wrong with this code. It should use left, but it
-    // does not.
-    fn double_left(key: Arc<K>, value: Arc<V>, _left: Arc<Map<K, V>>, right: Arc<Map<K, V>>) -> Map<K, V> {
+    fn double_left(key: Arc<K>, value: Arc<V>, left: Arc<Map<K, V>>, right: Arc<Map<K, V>>) -> Map<K, V> {
         match right.deref() {
             &Tip => panic!("irrefutable pattern match failed."),
             &Bin { key: ref kx, value: ref vx, left: ref lx, right: ref rx, .. } => {
@@ -269,7 +481,7 @@ impl<K: Send + Sync + Ord, V: Send + Sync> Map<K, V> {
                     &Tip => panic!("irrefutable pattern match failed."),
                     &Bin { key: ref ky, value: ref vy, left: ref ly, right: ref ry, .. } => {
                         Map::bin_ref(ky, vy,
-                                     &Arc::new(Map::bin(key, value, lx.clone(), ly.clone())),
+                                     &Arc::new(Map::bin(key, value, left, ly.clone())),
                                      &Arc::new(Map::bin_ref(kx, vx, ry, rx)))
                     }
                 }
@@ -296,161 +508,1128 @@ impl<K: Send + Sync + Ord, V: Send + Sync> Map<K, V> {
     // Glue two trees together, assuming that they are balanced with respect to
     // each other (all keys in left are smaller than all keys in right).
     fn glue(left: Arc<Map<K, V>>, right: Arc<Map<K, V>>) -> Map<K, V> {
-        match (left.deref(), right.deref()) {
+        let glued = match (left.deref(), right.deref()) {
             (&Tip, r) => r.clone(),
             (l, &Tip) => l.clone(),
             (l, r) => {
+                // Neither branch is empty, so popping cannot fail.
                 if l.len() > r.len() {
-                    let (km, max) = l.max().unwrap();
-                    let lx = Arc::new(l.delete_max().unwrap());
-                    Map::balance(km, max, lx, right.clone())
+                    let ((km, max), lx) = l.pop_max().unwrap();
+                    Map::balance(km, max, Arc::new(lx), right.clone())
                 } else {
-                    let (km, min) = r.min().unwrap();
-                    let rx = Arc::new(r.delete_min().unwrap());
-                    Map::balance(km, min, left.clone(), rx)
+                    let ((km, min), rx) = r.pop_min().unwrap();
+                    Map::balance(km, min, left.clone(), Arc::new(rx))
                 }
             }
+        };
+
+        glued.checked()
+    }
+
+    // Insert a key which is smaller than every key in the map.
+    fn insert_min(&self, key: Arc<K>, value: Arc<V>) -> Map<K, V> {
+        match *self {
+            Tip => Map::singleton_arc(key, value),
+            Bin { key: ref kx, value: ref vx, ref left, ref right, .. } => {
+                Map::balance(kx.clone(), vx.clone(), Arc::new(left.insert_min(key, value)), right.clone())
+            }
+        }
+    }
+
+    // Insert a key which is larger than every key in the map.
+    fn insert_max(&self, key: Arc<K>, value: Arc<V>) -> Map<K, V> {
+        match *self {
+            Tip => Map::singleton_arc(key, value),
+            Bin { key: ref kx, value: ref vx, ref left, ref right, .. } => {
+                Map::balance(kx.clone(), vx.clone(), left.clone(), Arc::new(right.insert_max(key, value)))
+            }
         }
     }
 }
 
-// Deletion
+// Validation
 impl<K: Send + Sync + Ord, V: Send + Sync> Map<K, V> {
-    /// Delete a key and its value from the map.
+    /// Check that the map satisfies all of its invariants.
     ///
-    /// If the key is not a member of the map, the original map is returned.
-    pub fn delete(&self, key: &K) -> Map<K, V> {
+    /// The keys must be in ascending order, the size cached in every node
+    /// must be correct, and the branches of every node must be balanced with
+    /// respect to each other. This walks the whole map, and reports the first
+    /// violation found in inorder.
+    ///
+    /// Building with the `validate` feature runs this check on every map
+    /// produced by balancing, linking or merging in debug builds, which
+    /// covers every operation which changes the shape of a map.
+    pub fn validate(&self) -> Result<(), InvariantError> {
+        self.check(None, None, 0).map(|_| ())
+    }
+
+    // Check the subtree whose smallest key is at inorder index `offset`, and
+    // whose keys must lie strictly between `min` and `max`. Returns its
+    // actual size.
+    fn check(&self, min: Option<&K>, max: Option<&K>, offset: uint) -> Result<uint, InvariantError> {
         match *self {
-            Tip => Tip,
-            Bin { key: ref kx, value: ref vx, left: ref l, right: ref r, .. } => {
-                match key.cmp(&**kx) {
-                    Less    => Map::balance(kx.clone(), vx.clone(), Arc::new(l.delete(key)), r.clone()),
-                    Greater => Map::balance(kx.clone(), vx.clone(), l.clone(), Arc::new(r.delete(key))),
-                    Equal   => Map::glue(l.clone(), r.clone())
+            Tip => Ok(0),
+            Bin { size, ref key, ref left, ref right, .. } => {
+                let lsize = try!(left.check(min, Some(&**key), offset));
+                let index = offset + lsize;
+
+                if min.map_or(false, |min| **key <= *min) || max.map_or(false, |max| **key >= *max) {
+                    return Err(InvariantError::Unordered { index: index })
                 }
+
+                let rsize = try!(right.check(Some(&**key), max, index + 1));
+                let actual = lsize + rsize + 1;
+
+                if size != actual {
+                    return Err(InvariantError::WrongSize { index: index, cached: size, actual: actual })
+                }
+
+                if !is_balanced(lsize, rsize) {
+                    return Err(InvariantError::Unbalanced { index: index, left: lsize, right: rsize })
+                }
+
+                Ok(actual)
             }
         }
     }
+
+    // Panic if the map is invalid.
+    #[cfg(all(feature = "validate", not(ndebug)))]
+    fn checked(self) -> Map<K, V> {
+        match self.validate() {
+            Ok(()) => self,
+            Err(err) => panic!("map invariant violated: {}", err)
+        }
+    }
+
+    // Validation is disabled, so this is a no-op.
+    #[cfg(not(all(feature = "validate", not(ndebug))))]
+    #[inline(always)]
+    fn checked(self) -> Map<K, V> { self }
 }
 
-// Updates
+/// A violated map invariant, as reported by `Map::validate`.
+///
+/// Nodes are identified by the inorder index of their key.
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub enum InvariantError {
+    /// The key of this node is out of order with respect to its ancestors.
+    Unordered {
+        /// The index of the node.
+        index: uint
+    },
+
+    /// The size cached in this node is wrong.
+    WrongSize {
+        /// The index of the node.
+        index: uint,
+
+        /// The size cached in the node.
+        cached: uint,
+
+        /// The number of pairs actually in the node.
+        actual: uint
+    },
+
+    /// The branches of this node are not balanced.
+    Unbalanced {
+        /// The index of the node.
+        index: uint,
+
+        /// The size of the left branch.
+        left: uint,
+
+        /// The size of the right branch.
+        right: uint
+    }
+}
+
+impl Error for InvariantError {
+    fn description(&self) -> &str {
+        match *self {
+            InvariantError::Unordered { .. } => "map keys are out of order",
+            InvariantError::WrongSize { .. } => "map node has the wrong size",
+            InvariantError::Unbalanced { .. } => "map node is unbalanced"
+        }
+    }
+}
+
+// Splitting and joining
 impl<K: Send + Sync + Ord, V: Send + Sync> Map<K, V> {
-    /// Adjust the value at a specified key with the provided closure.
+    /// Split a map into the pairs with keys smaller than `key`, the value at
+    /// `key` if there is one, and the pairs with keys larger than `key`.
     ///
-    /// If they key is not a member of the map, the original map is returned.
-    pub fn adjust(&self, key: &K, modifier: |&V| -> V) -> Map<K, V> {
+    /// Use `link` to put the pieces back together.
+    pub fn split(&self, key: &K) -> (Map<K, V>, Option<Arc<V>>, Map<K, V>) {
+        let (lt, found, gt) = self.split_lookup(key);
+        (lt, found.map(|(_, value)| value), gt)
+    }
+
+    /// Split a map into the pairs with keys smaller than `key`, the pair at
+    /// `key` if there is one, and the pairs with keys larger than `key`.
+    pub fn split_lookup(&self, key: &K) -> (Map<K, V>, Option<(Arc<K>, Arc<V>)>, Map<K, V>) {
         match *self {
-            Tip => Tip,
-            Bin { key: ref kx, value: ref vx, left: ref l, right: ref r, .. } => {
+            Tip => (Tip, None, Tip),
+            Bin { key: ref kx, value: ref vx, ref left, ref right, .. } => {
                 match key.cmp(&**kx) {
-                    Less    => Map::balance(kx.clone(), vx.clone(), Arc::new(l.adjust(key, modifier)), r.clone()),
-                    Greater => Map::balance(kx.clone(), vx.clone(), l.clone(), Arc::new(r.adjust(key, modifier))),
-                    Equal   => Map::bin(kx.clone(), Arc::new(modifier(&**vx)), l.clone(), r.clone())
+                    Less => {
+                        let (lt, found, gt) = left.split_lookup(key);
+                        (lt, found, Map::link(kx.clone(), vx.clone(), Arc::new(gt), right.clone()))
+                    },
+                    Greater => {
+                        let (lt, found, gt) = right.split_lookup(key);
+                        (Map::link(kx.clone(), vx.clone(), left.clone(), Arc::new(lt)), found, gt)
+                    },
+                    Equal => ((**left).clone(), Some((kx.clone(), vx.clone())), (**right).clone())
                 }
             }
         }
     }
 
-    /// Conditionally update the key in the map with the provided closure. If the closure
-    /// returns None, then the key value pair is deleted.
-    pub fn update(&self, key: &K, modifier: |&V| -> Option<V>) -> Map<K, V> {
-        match *self {
-            Tip => Tip,
-            Bin { key: ref kx, value: ref vx, left: ref l, right: ref r, .. } => {
-                match key.cmp(&**kx) {
-                    Less    => Map::balance(kx.clone(), vx.clone(), Arc::new(l.update(key, modifier)), r.clone()),
-                    Greater => Map::balance(kx.clone(), vx.clone(), l.clone(), Arc::new(r.update(key, modifier))),
-                    Equal   => {
-                        match modifier(&**vx) {
-                            // Alter the key at this value
-                            Some(val) => Map::bin(kx.clone(), Arc::new(val), l.clone(), r.clone()),
-                            // Delete this key from the map
-                            None => Map::glue(l.clone(), r.clone())
-                        }
-                    }
+    /// Join two maps of any relative size with a pivot pair, sometimes called
+    /// `join`.
+    ///
+    /// All keys in `left` must be smaller than `key` and all keys in `right`
+    /// must be larger, otherwise the resulting map is not ordered.
+    pub fn link(key: Arc<K>, value: Arc<V>, left: Arc<Map<K, V>>, right: Arc<Map<K, V>>) -> Map<K, V> {
+        let linked = match (left.deref(), right.deref()) {
+            (&Tip, r) => r.insert_min(key, value),
+            (l, &Tip) => l.insert_max(key, value),
+            (&Bin { key: ref ky, value: ref vy, left: ref ly, right: ref ry, size: sl },
+             &Bin { key: ref kz, value: ref vz, left: ref lz, right: ref rz, size: sr }) => {
+                if DELTA * sl < sr {
+                    Map::balance(kz.clone(), vz.clone(),
+                                 Arc::new(Map::link(key, value, left.clone(), lz.clone())), rz.clone())
+                } else if DELTA * sr < sl {
+                    Map::balance(ky.clone(), vy.clone(),
+                                 ly.clone(), Arc::new(Map::link(key, value, ry.clone(), right.clone())))
+                } else {
+                    Map::bin(key, value, left.clone(), right.clone())
                 }
             }
-        }
+        };
+
+        linked.checked()
     }
 
-    /// Alter the value at the provided key, can be used to update, insert, or
-    /// delete from the map.
+    /// Concatenate two maps of any relative size without a pivot.
     ///
-    /// The provided closure is called with `Some(&key)`, `Some(&value)` if the key is found, and
-    /// None if it is not found. If the closure returns Some(value) then that
-    /// value replaces the value currently at that key in the map or inserts
-    /// the value into the map; if it returns None then that key value pair
-    /// will be deleted or will remain not-inserted.
-    pub fn alter(&self, key: Arc<K>, modifier: |Option<&K>, Option<&V>| -> Option<V>) -> Map<K, V> {
-        match *self {
-            Tip => {
-                match modifier(None, None) {
-                    // Insert this key into the map.
-                    Some(val) => Map::singleton_arc(key, Arc::new(val)),
-                    // Stay not-inserted.
-                    None => Tip
-                }
-            },
-            Bin { key: ref kx, value: ref vx, left: ref l, right: ref r, .. } => {
-                match key.cmp(&*kx) {
-                    Less    => Map::balance(kx.clone(), vx.clone(), Arc::new(l.alter(key, modifier)), r.clone()),
-                    Greater => Map::balance(kx.clone(), vx.clone(), l.clone(), Arc::new(r.alter(key, modifier))),
-                    Equal   => {
-                        match modifier(Some(&**kx), Some(&**vx)) {
-                            // Alter the key at this value
-                            Some(val) => Map::bin(kx.clone(), Arc::new(val), l.clone(), r.clone()),
-                            // Delete this key from the map
-                            None => Map::glue(l.clone(), r.clone())
-                        }
-                    }
+    /// All keys in `left` must be smaller than all keys in `right`, otherwise
+    /// the resulting map is not ordered.
+    pub fn merge(left: Arc<Map<K, V>>, right: Arc<Map<K, V>>) -> Map<K, V> {
+        let merged = match (left.deref(), right.deref()) {
+            (&Tip, r) => r.clone(),
+            (l, &Tip) => l.clone(),
+            (&Bin { key: ref kx, value: ref vx, left: ref lx, right: ref rx, size: sl },
+             &Bin { key: ref ky, value: ref vy, left: ref ly, right: ref ry, size: sr }) => {
+                if DELTA * sl < sr {
+                    Map::balance(ky.clone(), vy.clone(),
+                                 Arc::new(Map::merge(left.clone(), ly.clone())), ry.clone())
+                } else if DELTA * sr < sl {
+                    Map::balance(kx.clone(), vx.clone(),
+                                 lx.clone(), Arc::new(Map::merge(rx.clone(), right.clone())))
+                } else {
+                    Map::glue(left.clone(), right.clone())
                 }
             }
-        }
+        };
+
+        merged.checked()
     }
 }
 
-// Min/Max
+// Set operations
+//
+// These are all divide and conquer algorithms: one map is split around the
+// root of the other and the results are recombined with link or merge, so
+// subtrees which are not touched are shared rather than copied.
 impl<K: Send + Sync + Ord, V: Send + Sync> Map<K, V> {
-    /// Find the minimum pair in the map.
-    pub fn min(&self) -> Option<(Arc<K>, Arc<V>)> {
-        match *self {
-            Tip => None,
-            Bin { ref left, ref right, ref key, ref value, .. } => {
-                match (left.deref(), right.deref()) {
-                    // This is a tree with a right pointer only.
-                    // Return the current val because it is the min.
-                    (&Tip, _) => Some((key.clone(), value.clone())),
-                    // This is a tree with a left pointer. Recurse on it.
-                    (ref ll, _) => ll.min()
-                }
+    /// The union of two maps.
+    ///
+    /// If a key is present in both maps, the value from `self` is used.
+    pub fn union(&self, other: &Map<K, V>) -> Map<K, V> {
+        match (self, other) {
+            (_, &Tip) => self.clone(),
+            (&Tip, _) => other.clone(),
+            (&Bin { key: ref kx, value: ref vx, ref left, ref right, .. }, _) => {
+                let (lt, _, gt) = other.split_lookup(&**kx);
+                Map::link(kx.clone(), vx.clone(),
+                          Arc::new(left.union(&lt)), Arc::new(right.union(&gt)))
             }
         }
     }
 
-    /// Find the maximum pair in the map.
-    pub fn max(&self) -> Option<(Arc<K>, Arc<V>)> {
-        match *self {
-            Tip => None,
-            Bin { ref left, ref right, ref key, ref value, .. } => {
-                match (left.deref(), right.deref()) {
-                    // This is a tree with a left pointer only.
-                    // The current val is the max.
-                    (_, &Tip) => Some((key.clone(), value.clone())),
-                    // This is a tree with a right pointer. Recurse on it.
-                    (_, ref rr) => rr.min()
+    /// The union of two maps, combining the values of keys present in both
+    /// maps with the provided closure.
+    ///
+    /// The closure is called with the key, the value from `self` and the
+    /// value from `other`.
+    pub fn union_with(&self, other: &Map<K, V>, combine: |&K, &V, &V| -> V) -> Map<K, V> {
+        match (self, other) {
+            (_, &Tip) => self.clone(),
+            (&Tip, _) => other.clone(),
+            (&Bin { key: ref kx, value: ref vx, ref left, ref right, .. }, _) => {
+                let (lt, found, gt) = other.split_lookup(&**kx);
+                let value = match found {
+                    Some((_, ref vy)) => Arc::new(combine(&**kx, &**vx, &**vy)),
+                    None => vx.clone()
+                };
+                let left = left.union_with(&lt, |k, a, b| combine(k, a, b));
+                let right = right.union_with(&gt, |k, a, b| combine(k, a, b));
+                Map::link(kx.clone(), value, Arc::new(left), Arc::new(right))
+            }
+        }
+    }
+
+    /// The intersection of two maps.
+    ///
+    /// The values from `self` are used.
+    pub fn intersection<W: Send + Sync>(&self, other: &Map<K, W>) -> Map<K, V> {
+        match (self, other) {
+            (&Tip, _) | (_, &Tip) => Tip,
+            (&Bin { key: ref kx, value: ref vx, ref left, ref right, .. }, _) => {
+                let (lt, found, gt) = other.split_lookup(&**kx);
+                let left = Arc::new(left.intersection(&lt));
+                let right = Arc::new(right.intersection(&gt));
+                match found {
+                    Some(_) => Map::link(kx.clone(), vx.clone(), left, right),
+                    None => Map::merge(left, right)
                 }
             }
         }
     }
 
-    /// Delete the minimum element in the map.
+    /// The intersection of two maps, combining the values of each key with
+    /// the provided closure.
     ///
-    /// Returns None if the map is empty.
-    pub fn delete_min(&self) -> Option<Map<K, V>> {
-        match *self {
-            Tip => None,
-            Bin { ref left, ref right, ref key, ref value, .. } => {
-                match (left.deref(), right.deref()) {
+    /// The closure is called with the key, the value from `self` and the
+    /// value from `other`.
+    pub fn intersection_with<W: Send + Sync, X: Send + Sync>(&self, other: &Map<K, W>,
+                                                             combine: |&K, &V, &W| -> X) -> Map<K, X> {
+        match (self, other) {
+            (&Tip, _) | (_, &Tip) => Tip,
+            (&Bin { key: ref kx, value: ref vx, ref left, ref right, .. }, _) => {
+                let (lt, found, gt) = other.split_lookup(&**kx);
+                let left = Arc::new(left.intersection_with(&lt, |k, a, b| combine(k, a, b)));
+                let right = Arc::new(right.intersection_with(&gt, |k, a, b| combine(k, a, b)));
+                match found {
+                    Some((_, ref vy)) => Map::link(kx.clone(), Arc::new(combine(&**kx, &**vx, &**vy)),
+                                                   left, right),
+                    None => Map::merge(left, right)
+                }
+            }
+        }
+    }
+
+    /// The pairs of `self` whose keys are not in `other`.
+    pub fn difference<W: Send + Sync>(&self, other: &Map<K, W>) -> Map<K, V> {
+        match (self, other) {
+            (&Tip, _) => Tip,
+            (_, &Tip) => self.clone(),
+            (_, &Bin { ref key, ref left, ref right, .. }) => {
+                let (lt, _, gt) = self.split_lookup(&**key);
+                let result = Map::merge(Arc::new(lt.difference(&**left)),
+                                        Arc::new(gt.difference(&**right)));
+
+                // Nothing was removed, so share the original instead.
+                if result.len() == self.len() { self.clone() } else { result }
+            }
+        }
+    }
+
+    /// The pairs whose keys are in exactly one of the two maps.
+    pub fn symmetric_difference(&self, other: &Map<K, V>) -> Map<K, V> {
+        match (self, other) {
+            (&Tip, _) => other.clone(),
+            (_, &Tip) => self.clone(),
+            (&Bin { key: ref kx, value: ref vx, ref left, ref right, .. }, _) => {
+                let (lt, found, gt) = other.split_lookup(&**kx);
+                let left = Arc::new(left.symmetric_difference(&lt));
+                let right = Arc::new(right.symmetric_difference(&gt));
+                match found {
+                    Some(_) => Map::merge(left, right),
+                    None => Map::link(kx.clone(), vx.clone(), left, right)
+                }
+            }
+        }
+    }
+
+    /// Are all the keys of this map also keys of `other`?
+    pub fn is_subset<W: Send + Sync>(&self, other: &Map<K, W>) -> bool {
+        self.len() <= other.len() && self.keys_within(other)
+    }
+
+    /// Do these maps have no keys in common?
+    pub fn is_disjoint<W: Send + Sync>(&self, other: &Map<K, W>) -> bool {
+        match (self, other) {
+            (&Tip, _) | (_, &Tip) => true,
+            (&Bin { ref key, ref left, ref right, .. }, _) => {
+                let (lt, found, gt) = other.split_lookup(&**key);
+                found.is_none() && left.is_disjoint(&lt) && right.is_disjoint(&gt)
+            }
+        }
+    }
+
+    // is_subset without the initial size check.
+    fn keys_within<W: Send + Sync>(&self, other: &Map<K, W>) -> bool {
+        match *self {
+            Tip => true,
+            Bin { ref key, ref left, ref right, .. } => {
+                let (lt, found, gt) = other.split_lookup(&**key);
+                found.is_some() && left.keys_within(&lt) && right.keys_within(&gt)
+            }
+        }
+    }
+}
+
+// Transformations
+impl<K: Send + Sync, V: Send + Sync> Map<K, V> {
+    /// Create a new map by applying a closure to every value.
+    ///
+    /// The new map has the same shape as this one, and shares its keys.
+    pub fn map_values<W: Send + Sync>(&self, f: |&V| -> W) -> Map<K, W> {
+        self.map_with_key(|_, value| f(value))
+    }
+
+    /// Create a new map by applying a closure to every key and value.
+    ///
+    /// The closure is called in ascending order of keys. The new map has the
+    /// same shape as this one, and shares its keys.
+    pub fn map_with_key<W: Send + Sync>(&self, f: |&K, &V| -> W) -> Map<K, W> {
+        match *self {
+            Tip => Tip,
+            Bin { size, ref key, ref value, ref left, ref right } => {
+                let left = left.map_with_key(|k, v| f(k, v));
+                let value = f(&**key, &**value);
+                let right = right.map_with_key(|k, v| f(k, v));
+
+                Bin {
+                    size: size,
+                    key: key.clone(),
+                    value: Arc::new(value),
+                    left: Arc::new(left),
+                    right: Arc::new(right)
+                }
+            }
+        }
+    }
+}
+
+impl<K: Send + Sync + Ord, V: Send + Sync> Map<K, V> {
+    /// Create a new map containing only the pairs for which the predicate
+    /// returns true.
+    ///
+    /// Subtrees where every pair is kept are shared with this map.
+    pub fn filter(&self, predicate: |&K, &V| -> bool) -> Map<K, V> {
+        match *self {
+            Tip => Tip,
+            Bin { ref key, ref value, ref left, ref right, .. } => {
+                let lx = reuse(left, left.filter(|k, v| predicate(k, v)));
+                let keep = predicate(&**key, &**value);
+                let rx = reuse(right, right.filter(|k, v| predicate(k, v)));
+
+                if !keep {
+                    Map::merge(lx, rx)
+                } else if lx.len() == left.len() && rx.len() == right.len() {
+                    self.clone()
+                } else {
+                    Map::link(key.clone(), value.clone(), lx, rx)
+                }
+            }
+        }
+    }
+
+    /// Create a new map by applying a closure to every pair, keeping only the
+    /// values for which it returns Some.
+    pub fn filter_map<W: Send + Sync>(&self, f: |&K, &V| -> Option<W>) -> Map<K, W> {
+        match *self {
+            Tip => Tip,
+            Bin { ref key, ref value, ref left, ref right, .. } => {
+                let lx = Arc::new(left.filter_map(|k, v| f(k, v)));
+                let value = f(&**key, &**value);
+                let rx = Arc::new(right.filter_map(|k, v| f(k, v)));
+
+                match value {
+                    Some(value) => Map::link(key.clone(), Arc::new(value), lx, rx),
+                    None => Map::merge(lx, rx)
+                }
+            }
+        }
+    }
+
+    /// Split a map into the pairs for which the predicate returns true and
+    /// the pairs for which it returns false.
+    pub fn partition(&self, predicate: |&K, &V| -> bool) -> (Map<K, V>, Map<K, V>) {
+        match *self {
+            Tip => (Tip, Tip),
+            Bin { ref key, ref value, ref left, ref right, .. } => {
+                let (lt, lf) = left.partition(|k, v| predicate(k, v));
+                let keep = predicate(&**key, &**value);
+                let (rt, rf) = right.partition(|k, v| predicate(k, v));
+                let (lt, lf) = (reuse(left, lt), reuse(left, lf));
+                let (rt, rf) = (reuse(right, rt), reuse(right, rf));
+
+                if keep {
+                    (Map::link(key.clone(), value.clone(), lt, rt), Map::merge(lf, rf))
+                } else {
+                    (Map::merge(lt, rt), Map::link(key.clone(), value.clone(), lf, rf))
+                }
+            }
+        }
+    }
+
+    /// Split a map in two by applying a closure to every pair. Values for
+    /// which it returns `Ok` go in the first map, and values for which it
+    /// returns `Err` go in the second.
+    pub fn map_either<W: Send + Sync, X: Send + Sync>(&self, f: |&K, &V| -> Result<W, X>)
+                                                     -> (Map<K, W>, Map<K, X>) {
+        match *self {
+            Tip => (Tip, Tip),
+            Bin { ref key, ref value, ref left, ref right, .. } => {
+                let (lw, lx) = left.map_either(|k, v| f(k, v));
+                let value = f(&**key, &**value);
+                let (rw, rx) = right.map_either(|k, v| f(k, v));
+                let (lw, lx) = (Arc::new(lw), Arc::new(lx));
+                let (rw, rx) = (Arc::new(rw), Arc::new(rx));
+
+                match value {
+                    Ok(w) => (Map::link(key.clone(), Arc::new(w), lw, rw), Map::merge(lx, rx)),
+                    Err(x) => (Map::merge(lw, rw), Map::link(key.clone(), Arc::new(x), lx, rx))
+                }
+            }
+        }
+    }
+}
+
+// Share the original subtree instead of a filtered copy if nothing was
+// filtered out of it.
+fn reuse<K: Send + Sync, V: Send + Sync>(original: &Arc<Map<K, V>>, filtered: Map<K, V>) -> Arc<Map<K, V>> {
+    if filtered.len() == original.len() { original.clone() } else { Arc::new(filtered) }
+}
+
+// Folds
+//
+// The public folds hand a single &mut reference to their closure down the
+// recursion, rather than wrapping it in a new closure at every level.
+impl<K: Send + Sync, V: Send + Sync> Map<K, V> {
+    /// Fold over the values of the map in ascending order of keys.
+    pub fn fold<B>(&self, init: B, f: |B, &V| -> B) -> B {
+        self.fold_with_key(init, |acc, _, value| f(acc, value))
+    }
+
+    /// Fold over the values of the map in descending order of keys.
+    pub fn fold_right<B>(&self, init: B, f: |&V, B| -> B) -> B {
+        self.fold_right_with_key(init, |_, value, acc| f(value, acc))
+    }
+
+    /// Fold over the pairs of the map in ascending order of keys.
+    pub fn fold_with_key<B>(&self, init: B, mut f: |B, &K, &V| -> B) -> B {
+        self.fold_left_ref(init, &mut f)
+    }
+
+    /// Fold over the pairs of the map in descending order of keys.
+    pub fn fold_right_with_key<B>(&self, init: B, mut f: |&K, &V, B| -> B) -> B {
+        self.fold_right_ref(init, &mut f)
+    }
+
+    /// Fold over the pairs of the map in ascending order of keys, stopping
+    /// at the first error.
+    pub fn try_fold<B, E>(&self, init: B, mut f: |B, &K, &V| -> Result<B, E>) -> Result<B, E> {
+        self.try_fold_ref(init, &mut f)
+    }
+
+    /// Create a new map by applying a fallible closure to every pair in
+    /// ascending order of keys, stopping at the first error.
+    ///
+    /// Like `map_with_key`, the new map has the same shape as this one.
+    pub fn traverse<W: Send + Sync, E>(&self, mut f: |&K, &V| -> Result<W, E>) -> Result<Map<K, W>, E> {
+        self.traverse_ref(&mut f)
+    }
+
+    fn fold_left_ref<B>(&self, init: B, f: &mut |B, &K, &V| -> B) -> B {
+        match *self {
+            Tip => init,
+            Bin { ref key, ref value, ref left, ref right, .. } => {
+                let acc = left.fold_left_ref(init, f);
+                let acc = (*f)(acc, &**key, &**value);
+                right.fold_left_ref(acc, f)
+            }
+        }
+    }
+
+    fn fold_right_ref<B>(&self, init: B, f: &mut |&K, &V, B| -> B) -> B {
+        match *self {
+            Tip => init,
+            Bin { ref key, ref value, ref left, ref right, .. } => {
+                let acc = right.fold_right_ref(init, f);
+                let acc = (*f)(&**key, &**value, acc);
+                left.fold_right_ref(acc, f)
+            }
+        }
+    }
+
+    fn try_fold_ref<B, E>(&self, init: B, f: &mut |B, &K, &V| -> Result<B, E>) -> Result<B, E> {
+        match *self {
+            Tip => Ok(init),
+            Bin { ref key, ref value, ref left, ref right, .. } => {
+                let acc = try!(left.try_fold_ref(init, f));
+                let acc = try!((*f)(acc, &**key, &**value));
+                right.try_fold_ref(acc, f)
+            }
+        }
+    }
+
+    fn traverse_ref<W: Send + Sync, E>(&self, f: &mut |&K, &V| -> Result<W, E>) -> Result<Map<K, W>, E> {
+        match *self {
+            Tip => Ok(Tip),
+            Bin { size, ref key, ref value, ref left, ref right } => {
+                let left = try!(left.traverse_ref(f));
+                let value = try!((*f)(&**key, &**value));
+                let right = try!(right.traverse_ref(f));
+
+                Ok(Bin {
+                    size: size,
+                    key: key.clone(),
+                    value: Arc::new(value),
+                    left: Arc::new(left),
+                    right: Arc::new(right)
+                })
+            }
+        }
+    }
+}
+
+// Entries
+impl<K: Send + Sync + Ord, V: Send + Sync> Map<K, V> {
+    /// Find the entry for a key in the map.
+    ///
+    /// The tree is only searched once. The path to the key is recorded in the
+    /// entry, and the new version of the map is built from it by methods such
+    /// as `or_insert` or `remove`, copying just that one path.
+    pub fn entry<'a>(&'a self, key: Arc<K>) -> Entry<'a, K, V> {
+        let mut path = Vec::new();
+        let mut node = self;
+
+        loop {
+            match *node {
+                Tip => return Vacant(VacantEntry {
+                    map: self,
+                    path: path,
+                    key: key
+                }),
+                Bin { key: ref kx, value: ref vx, ref left, ref right, .. } => {
+                    match (*key).cmp(&**kx) {
+                        Less    => {
+                            path.push((node, Left));
+                            node = &**left;
+                        },
+                        Greater => {
+                            path.push((node, Right));
+                            node = &**right;
+                        },
+                        Equal   => return Occupied(OccupiedEntry {
+                            map: self,
+                            path: path,
+                            node: node,
+                            value: vx.clone(),
+                            modified: false
+                        })
+                    }
+                }
+            }
+        }
+    }
+
+    // Rebuild a path from the root around a replacement for the subtree at
+    // its end, rebalancing on the way up.
+    fn rebuild(path: Vec<(&Map<K, V>, Side)>, subtree: Map<K, V>) -> Map<K, V> {
+        path.into_iter().rev().fold(subtree, |child, (node, side)| {
+            match *node {
+                Tip => panic!("irrefutable pattern match failed."),
+                Bin { ref key, ref value, ref left, ref right, .. } => match side {
+                    Left  => Map::balance(key.clone(), value.clone(), Arc::new(child), right.clone()),
+                    Right => Map::balance(key.clone(), value.clone(), left.clone(), Arc::new(child))
+                }
+            }
+        })
+    }
+}
+
+// Which branch of a node the path to an entry took.
+enum Side {
+    Left,
+    Right
+}
+
+/// A view into a single key of a map, which may or may not be present.
+///
+/// Obtained from `Map::entry`.
+pub enum Entry<'a, K: 'a, V: 'a> {
+    /// The key is in the map.
+    Occupied(OccupiedEntry<'a, K, V>),
+
+    /// The key is not in the map.
+    Vacant(VacantEntry<'a, K, V>)
+}
+
+/// An entry for a key which is in the map.
+pub struct OccupiedEntry<'a, K: 'a, V: 'a> {
+    map: &'a Map<K, V>,
+    path: Vec<(&'a Map<K, V>, Side)>,
+
+    // The node holding the key.
+    node: &'a Map<K, V>,
+
+    // The value for the key, which may have been changed by `and_modify`.
+    value: Arc<V>,
+    modified: bool
+}
+
+/// An entry for a key which is not in the map.
+pub struct VacantEntry<'a, K: 'a, V: 'a> {
+    map: &'a Map<K, V>,
+    path: Vec<(&'a Map<K, V>, Side)>,
+    key: Arc<K>
+}
+
+impl<'a, K: Send + Sync + Ord, V: Send + Sync> Entry<'a, K, V> {
+    /// The key of this entry.
+    pub fn key(&self) -> &K {
+        match *self {
+            Occupied(ref entry) => entry.key(),
+            Vacant(ref entry) => entry.key()
+        }
+    }
+
+    /// Get the new map, inserting the value if the key is not present.
+    pub fn or_insert(self, value: Arc<V>) -> Map<K, V> {
+        match self {
+            Occupied(entry) => entry.into_map(),
+            Vacant(entry) => entry.insert(value)
+        }
+    }
+
+    /// Get the new map, inserting the result of the closure if the key is not
+    /// present.
+    pub fn or_insert_with(self, default: || -> V) -> Map<K, V> {
+        match self {
+            Occupied(entry) => entry.into_map(),
+            Vacant(entry) => entry.insert(Arc::new(default()))
+        }
+    }
+
+    /// Modify the value with the provided closure if the key is present.
+    pub fn and_modify(self, modifier: |&V| -> V) -> Entry<'a, K, V> {
+        match self {
+            Occupied(mut entry) => {
+                entry.value = Arc::new(modifier(&*entry.value));
+                entry.modified = true;
+                Occupied(entry)
+            },
+            Vacant(entry) => Vacant(entry)
+        }
+    }
+
+    /// Get the new map, with any modifications made through this entry.
+    pub fn into_map(self) -> Map<K, V> {
+        match self {
+            Occupied(entry) => entry.into_map(),
+            Vacant(entry) => entry.into_map()
+        }
+    }
+}
+
+impl<'a, K: Send + Sync + Ord, V: Send + Sync> OccupiedEntry<'a, K, V> {
+    /// The key of this entry.
+    pub fn key(&self) -> &K {
+        match *self.node {
+            Bin { ref key, .. } => &**key,
+            Tip => panic!("irrefutable pattern match failed.")
+        }
+    }
+
+    /// The value of this entry.
+    pub fn get(&self) -> &V { &*self.value }
+
+    /// Get the new map, with the value of this entry replaced.
+    pub fn replace(mut self, value: Arc<V>) -> Map<K, V> {
+        self.value = value;
+        self.modified = true;
+        self.into_map()
+    }
+
+    /// Get the new map, with this entry removed.
+    pub fn remove(self) -> Map<K, V> {
+        let node = self.node;
+        match *node {
+            Bin { ref left, ref right, .. } => {
+                Map::rebuild(self.path, Map::glue(left.clone(), right.clone()))
+            },
+            Tip => panic!("irrefutable pattern match failed.")
+        }
+    }
+
+    /// Get the new map, with any modifications made through this entry.
+    pub fn into_map(self) -> Map<K, V> {
+        if !self.modified { return self.map.clone() }
+
+        let node = self.node;
+        match *node {
+            Bin { ref key, ref left, ref right, .. } => {
+                Map::rebuild(self.path, Map::bin_ref(key, &self.value, left, right))
+            },
+            Tip => panic!("irrefutable pattern match failed.")
+        }
+    }
+}
+
+impl<'a, K: Send + Sync + Ord, V: Send + Sync> VacantEntry<'a, K, V> {
+    /// The key of this entry.
+    pub fn key(&self) -> &K { &*self.key }
+
+    /// Get the new map, with the value inserted at this entry's key.
+    pub fn insert(self, value: Arc<V>) -> Map<K, V> {
+        Map::rebuild(self.path, Map::singleton_arc(self.key, value))
+    }
+
+    /// Get the new map, which is the original map.
+    pub fn into_map(self) -> Map<K, V> { self.map.clone() }
+}
+
+// Transients
+impl<K: Send + Sync + Ord, V: Send + Sync> Map<K, V> {
+    /// Get a transient version of this map, for fast batches of updates.
+    ///
+    /// The transient shares structure with this map, and only copies a node
+    /// the first time it modifies it. After that the node is uniquely owned
+    /// by the transient and is updated in place. Use `persistent` to turn the
+    /// transient back into a map.
+    pub fn transient(&self) -> TransientMap<K, V> {
+        TransientMap { root: Arc::new(self.clone()) }
+    }
+}
+
+/// A mutable builder for a map, obtained from `Map::transient`.
+///
+/// This is useful for loading many pairs at once, since inserting into a
+/// transient does not allocate a new path of nodes for every key.
+pub struct TransientMap<K, V> {
+    root: Arc<Map<K, V>>
+}
+
+impl<K: Send + Sync + Ord, V: Send + Sync> TransientMap<K, V> {
+    /// An empty transient map.
+    #[inline]
+    pub fn new() -> TransientMap<K, V> {
+        TransientMap { root: Arc::new(Tip) }
+    }
+
+    /// How many items are in the map.
+    #[inline]
+    pub fn len(&self) -> uint { self.root.len() }
+
+    /// Lookup a value in the map.
+    #[inline]
+    pub fn get<Sized? Q>(&self, key: &Q) -> Option<&V> where Q: Ord + BorrowFrom<K> {
+        self.root.get(key)
+    }
+
+    /// Is this key in the map?
+    #[inline]
+    pub fn contains<Sized? Q>(&self, key: &Q) -> bool where Q: Ord + BorrowFrom<K> {
+        self.root.contains(key)
+    }
+
+    /// Insert a key value pair into the map. If the key is already present in
+    /// the map, its value will be replaced.
+    pub fn insert(&mut self, key: Arc<K>, value: Arc<V>) {
+        insert_in_place(&mut self.root, key, value)
+    }
+
+    /// Delete a key and its value from the map.
+    pub fn delete<Sized? Q>(&mut self, key: &Q) where Q: Ord + BorrowFrom<K> {
+        // Don't copy the path to a key which isn't there.
+        if self.root.contains(key) {
+            delete_in_place(&mut self.root, key)
+        }
+    }
+
+    /// Freeze this transient into a persistent map.
+    pub fn persistent(self) -> Map<K, V> {
+        (*self.root).clone().checked()
+    }
+}
+
+impl<K: Send + Sync + Ord, V: Send + Sync> Extend<(K, V)> for TransientMap<K, V> {
+    fn extend<I: Iterator<(K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(Arc::new(key), Arc::new(value));
+        }
+    }
+}
+
+// Insert into the subtree behind this Arc, copying its root first if it is
+// shared.
+fn insert_in_place<K: Send + Sync + Ord, V: Send + Sync>(node: &mut Arc<Map<K, V>>, key: Arc<K>, value: Arc<V>) {
+    let node = node.make_unique();
+
+    match *node {
+        Tip => {
+            *node = Map::singleton_arc(key, value);
+            return
+        },
+        Bin { key: ref mut kx, value: ref mut vx, ref mut left, ref mut right, ref mut size } => {
+            match (*key).cmp(&**kx) {
+                Equal   => {
+                    *kx = key;
+                    *vx = value;
+                    return
+                },
+                Less    => insert_in_place(left, key, value),
+                Greater => insert_in_place(right, key, value)
+            }
+
+            *size = left.len() + right.len() + 1;
+        }
+    }
+
+    rebalance_in_place(node);
+}
+
+// Delete from the subtree behind this Arc, copying its root first if it is
+// shared.
+fn delete_in_place<K: Send + Sync + Ord, V: Send + Sync, Sized? Q>(node: &mut Arc<Map<K, V>>, key: &Q)
+        where Q: Ord + BorrowFrom<K> {
+    let node = node.make_unique();
+
+    let glued = match *node {
+        Tip => return,
+        Bin { key: ref kx, ref mut left, ref mut right, ref mut size, .. } => {
+            match key.cmp(BorrowFrom::borrow_from(&**kx)) {
+                Less    => {
+                    delete_in_place(left, key);
+                    *size -= 1;
+                    None
+                },
+                Greater => {
+                    delete_in_place(right, key);
+                    *size -= 1;
+                    None
+                },
+                // This is the node to delete.
+                Equal   => Some(Map::glue(left.clone(), right.clone()))
+            }
+        }
+    };
+
+    match glued {
+        Some(glued) => *node = glued,
+        None => rebalance_in_place(node)
+    }
+}
+
+// Restore the balance of a node after one of its branches has grown or
+// shrunk by one. Rotations allocate new nodes, but they are rare.
+fn rebalance_in_place<K: Send + Sync + Ord, V: Send + Sync>(node: &mut Map<K, V>) {
+    let rotated = match *node {
+        Tip => return,
+        Bin { ref key, ref value, ref left, ref right, .. } => {
+            if is_balanced(left.len(), right.len()) { return }
+            Map::balance(key.clone(), value.clone(), left.clone(), right.clone())
+        }
+    };
+
+    *node = rotated;
+}
+
+// Deletion
+impl<K: Send + Sync + Ord, V: Send + Sync> Map<K, V> {
+    /// Delete a key and its value from the map.
+    ///
+    /// If the key is not a member of the map, the original map is returned.
+    pub fn delete<Sized? Q>(&self, key: &Q) -> Map<K, V> where Q: Ord + BorrowFrom<K> {
+        match *self {
+            Tip => Tip,
+            Bin { key: ref kx, value: ref vx, left: ref l, right: ref r, .. } => {
+                match key.cmp(BorrowFrom::borrow_from(&**kx)) {
+                    Less    => Map::balance(kx.clone(), vx.clone(), Arc::new(l.delete(key)), r.clone()),
+                    Greater => Map::balance(kx.clone(), vx.clone(), l.clone(), Arc::new(r.delete(key))),
+                    Equal   => Map::glue(l.clone(), r.clone())
+                }
+            }
+        }
+    }
+
+    /// Remove a key from the map, returning the removed pair and the new map.
+    ///
+    /// Returns None if the key is not a member of the map.
+    pub fn remove<Sized? Q>(&self, key: &Q) -> Option<((Arc<K>, Arc<V>), Map<K, V>)>
+            where Q: Ord + BorrowFrom<K> {
+        match *self {
+            Tip => None,
+            Bin { key: ref kx, value: ref vx, left: ref l, right: ref r, .. } => {
+                match key.cmp(BorrowFrom::borrow_from(&**kx)) {
+                    Less    => l.remove(key).map(|(pair, lx)| {
+                        (pair, Map::balance(kx.clone(), vx.clone(), Arc::new(lx), r.clone()))
+                    }),
+                    Greater => r.remove(key).map(|(pair, rx)| {
+                        (pair, Map::balance(kx.clone(), vx.clone(), l.clone(), Arc::new(rx)))
+                    }),
+                    Equal   => Some(((kx.clone(), vx.clone()), Map::glue(l.clone(), r.clone())))
+                }
+            }
+        }
+    }
+}
+
+// Updates
+impl<K: Send + Sync + Ord, V: Send + Sync> Map<K, V> {
+    /// Adjust the value at a specified key with the provided closure.
+    ///
+    /// If they key is not a member of the map, the original map is returned.
+    pub fn adjust<Sized? Q>(&self, key: &Q, modifier: |&V| -> V) -> Map<K, V>
+            where Q: Ord + BorrowFrom<K> {
+        match *self {
+            Tip => Tip,
+            Bin { key: ref kx, value: ref vx, left: ref l, right: ref r, .. } => {
+                match key.cmp(BorrowFrom::borrow_from(&**kx)) {
+                    Less    => Map::balance(kx.clone(), vx.clone(), Arc::new(l.adjust(key, modifier)), r.clone()),
+                    Greater => Map::balance(kx.clone(), vx.clone(), l.clone(), Arc::new(r.adjust(key, modifier))),
+                    Equal   => Map::bin(kx.clone(), Arc::new(modifier(&**vx)), l.clone(), r.clone())
+                }
+            }
+        }
+    }
+
+    /// Conditionally update the key in the map with the provided closure. If the closure
+    /// returns None, then the key value pair is deleted.
+    pub fn update<Sized? Q>(&self, key: &Q, modifier: |&V| -> Option<V>) -> Map<K, V>
+            where Q: Ord + BorrowFrom<K> {
+        match *self {
+            Tip => Tip,
+            Bin { key: ref kx, value: ref vx, left: ref l, right: ref r, .. } => {
+                match key.cmp(BorrowFrom::borrow_from(&**kx)) {
+                    Less    => Map::balance(kx.clone(), vx.clone(), Arc::new(l.update(key, modifier)), r.clone()),
+                    Greater => Map::balance(kx.clone(), vx.clone(), l.clone(), Arc::new(r.update(key, modifier))),
+                    Equal   => {
+                        match modifier(&**vx) {
+                            // Alter the key at this value
+                            Some(val) => Map::bin(kx.clone(), Arc::new(val), l.clone(), r.clone()),
+                            // Delete this key from the map
+                            None => Map::glue(l.clone(), r.clone())
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Like `update`, but also returns the value at the key before it was
+    /// updated or deleted, if the key was present.
+    pub fn update_lookup<Sized? Q>(&self, key: &Q, modifier: |&V| -> Option<V>) -> (Option<Arc<V>>, Map<K, V>)
+            where Q: Ord + BorrowFrom<K> {
+        match *self {
+            Tip => (None, Tip),
+            Bin { key: ref kx, value: ref vx, left: ref l, right: ref r, .. } => {
+                match key.cmp(BorrowFrom::borrow_from(&**kx)) {
+                    Less    => {
+                        let (old, lx) = l.update_lookup(key, modifier);
+                        (old, Map::balance(kx.clone(), vx.clone(), Arc::new(lx), r.clone()))
+                    },
+                    Greater => {
+                        let (old, rx) = r.update_lookup(key, modifier);
+                        (old, Map::balance(kx.clone(), vx.clone(), l.clone(), Arc::new(rx)))
+                    },
+                    Equal   => {
+                        let updated = match modifier(&**vx) {
+                            Some(val) => Map::bin(kx.clone(), Arc::new(val), l.clone(), r.clone()),
+                            None => Map::glue(l.clone(), r.clone())
+                        };
+                        (Some(vx.clone()), updated)
+                    }
+                }
+            }
+        }
+    }
+
+    /// Alter the value at the provided key, can be used to update, insert, or
+    /// delete from the map.
+    ///
+    /// The provided closure is called with `Some(&key)`, `Some(&value)` if the key is found, and
+    /// None if it is not found. If the closure returns Some(value) then that
+    /// value replaces the value currently at that key in the map or inserts
+    /// the value into the map; if it returns None then that key value pair
+    /// will be deleted or will remain not-inserted.
+    pub fn alter(&self, key: Arc<K>, modifier: |Option<&K>, Option<&V>| -> Option<V>) -> Map<K, V> {
+        match *self {
+            Tip => {
+                match modifier(None, None) {
+                    // Insert this key into the map.
+                    Some(val) => Map::singleton_arc(key, Arc::new(val)),
+                    // Stay not-inserted.
+                    None => Tip
+                }
+            },
+            Bin { key: ref kx, value: ref vx, left: ref l, right: ref r, .. } => {
+                match key.cmp(&*kx) {
+                    Less    => Map::balance(kx.clone(), vx.clone(), Arc::new(l.alter(key, modifier)), r.clone()),
+                    Greater => Map::balance(kx.clone(), vx.clone(), l.clone(), Arc::new(r.alter(key, modifier))),
+                    Equal   => {
+                        match modifier(Some(&**kx), Some(&**vx)) {
+                            // Alter the key at this value
+                            Some(val) => Map::bin(kx.clone(), Arc::new(val), l.clone(), r.clone()),
+                            // Delete this key from the map
+                            None => Map::glue(l.clone(), r.clone())
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Min/Max
+impl<K: Send + Sync + Ord, V: Send + Sync> Map<K, V> {
+    /// Find the minimum pair in the map.
+    pub fn min(&self) -> Option<(Arc<K>, Arc<V>)> {
+        match *self {
+            Tip => None,
+            Bin { ref left, ref right, ref key, ref value, .. } => {
+                match (left.deref(), right.deref()) {
+                    // This is a tree with a right pointer only.
+                    // Return the current val because it is the min.
+                    (&Tip, _) => Some((key.clone(), value.clone())),
+                    // This is a tree with a left pointer. Recurse on it.
+                    (ref ll, _) => ll.min()
+                }
+            }
+        }
+    }
+
+    /// Find the maximum pair in the map.
+    pub fn max(&self) -> Option<(Arc<K>, Arc<V>)> {
+        match *self {
+            Tip => None,
+            Bin { ref left, ref right, ref key, ref value, .. } => {
+                match (left.deref(), right.deref()) {
+                    // This is a tree with a left pointer only.
+                    // The current val is the max.
+                    (_, &Tip) => Some((key.clone(), value.clone())),
+                    // This is a tree with a right pointer. Recurse on it.
+                    (_, ref rr) => rr.max()
+                }
+            }
+        }
+    }
+
+    /// Delete the minimum element in the map.
+    ///
+    /// Returns None if the map is empty.
+    pub fn delete_min(&self) -> Option<Map<K, V>> {
+        match *self {
+            Tip => None,
+            Bin { ref left, ref right, ref key, ref value, .. } => {
+                match (left.deref(), right.deref()) {
                     // This is a leaf, the min is the current value.
                     (&Tip, &Tip) => Some(Tip),
                     // This is a tree with a right pointer only.
@@ -468,126 +1647,762 @@ impl<K: Send + Sync + Ord, V: Send + Sync> Map<K, V> {
         }
     }
 
-    /// Delete the maximum element in the map.
+    /// Delete the maximum element in the map.
+    ///
+    /// Returns None if the map is empty.
+    pub fn delete_max(&self) -> Option<Map<K, V>> {
+        match *self {
+            Tip => None,
+            Bin { ref left, ref right, ref key, ref value, .. } => {
+                match (left.deref(), right.deref()) {
+                    // This is a leaf, the min is the current value.
+                    (&Tip, &Tip) => Some(Tip),
+                    // This is a tree with a left pointer only.
+                    // Return that left branch, because the
+                    // current val is the max.
+                    (ref ll, &Tip) => Some((**ll).clone()),
+                    // This is a tree with a right pointer. Recurse on it.
+                    // rr is not a tip, delete_max cannot fail.
+                    (_, ref rr) =>
+                        Some(Map::balance(key.clone(), value.clone(), left.clone(),
+                                          Arc::new(rr.delete_max().unwrap())))
+                }
+            }
+        }
+    }
+
+    /// Remove the minimum pair from the map, returning it and the new map.
+    ///
+    /// Returns None if the map is empty.
+    pub fn pop_min(&self) -> Option<((Arc<K>, Arc<V>), Map<K, V>)> {
+        match *self {
+            Tip => None,
+            Bin { ref left, ref right, ref key, ref value, .. } => {
+                match left.pop_min() {
+                    // The left branch is empty, so the current pair is the min.
+                    None => Some(((key.clone(), value.clone()), (**right).clone())),
+                    Some((min, lx)) => {
+                        Some((min, Map::balance(key.clone(), value.clone(), Arc::new(lx), right.clone())))
+                    }
+                }
+            }
+        }
+    }
+
+    /// Remove the maximum pair from the map, returning it and the new map.
+    ///
+    /// Returns None if the map is empty.
+    pub fn pop_max(&self) -> Option<((Arc<K>, Arc<V>), Map<K, V>)> {
+        match *self {
+            Tip => None,
+            Bin { ref left, ref right, ref key, ref value, .. } => {
+                match right.pop_max() {
+                    // The right branch is empty, so the current pair is the max.
+                    None => Some(((key.clone(), value.clone()), (**left).clone())),
+                    Some((max, rx)) => {
+                        Some((max, Map::balance(key.clone(), value.clone(), left.clone(), Arc::new(rx))))
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Indexing
+//
+// These use the sizes cached in each node, so they all run in logarithmic
+// time. Indices are positions in the inorder traversal of the map.
+impl<K: Send + Sync + Ord, V: Send + Sync> Map<K, V> {
+    /// Find the index of a key in the map.
+    pub fn index_of<Sized? Q>(&self, key: &Q) -> Option<uint> where Q: Ord + BorrowFrom<K> {
+        let mut node = self;
+        let mut index = 0;
+
+        loop {
+            match *node {
+                Tip => return None,
+                Bin { key: ref kx, ref left, ref right, .. } => {
+                    match key.cmp(BorrowFrom::borrow_from(&**kx)) {
+                        Less    => node = &**left,
+                        Greater => {
+                            index += left.len() + 1;
+                            node = &**right;
+                        },
+                        Equal   => return Some(index + left.len())
+                    }
+                }
+            }
+        }
+    }
+
+    /// Get the pair at an index in the map.
+    pub fn elem_at<'a>(&'a self, index: uint) -> Option<(&'a K, &'a V)> {
+        let mut node = self;
+        let mut index = index;
+
+        loop {
+            match *node {
+                Tip => return None,
+                Bin { ref key, ref value, ref left, ref right, .. } => {
+                    match index.cmp(&left.len()) {
+                        Less    => node = &**left,
+                        Greater => {
+                            index -= left.len() + 1;
+                            node = &**right;
+                        },
+                        Equal   => return Some((&**key, &**value))
+                    }
+                }
+            }
+        }
+    }
+
+    /// Delete the pair at an index in the map.
     ///
-    /// Returns None if the map is empty.
-    pub fn delete_max(&self) -> Option<Map<K, V>> {
+    /// Returns None if the index is out of bounds.
+    pub fn delete_at(&self, index: uint) -> Option<Map<K, V>> {
         match *self {
             Tip => None,
-            Bin { ref left, ref right, ref key, ref value, .. } => {
-                match (left.deref(), right.deref()) {
-                    // This is a leaf, the min is the current value.
-                    (&Tip, &Tip) => Some(Tip),
-                    // This is a tree with a left pointer only.
-                    // Return that left branch, because the
-                    // current val is the max.
-                    (ref ll, &Tip) => Some((**ll).clone()),
-                    // This is a tree with a right pointer. Recurse on it.
-                    // rr is not a tip, delete_max cannot fail.
-                    (_, ref rr) =>
-                        Some(Map::balance(key.clone(), value.clone(), left.clone(),
-                                          Arc::new(rr.delete_max().unwrap())))
+            Bin { key: ref kx, value: ref vx, ref left, ref right, .. } => {
+                match index.cmp(&left.len()) {
+                    Less    => left.delete_at(index).map(|l| {
+                        Map::balance(kx.clone(), vx.clone(), Arc::new(l), right.clone())
+                    }),
+                    Greater => right.delete_at(index - left.len() - 1).map(|r| {
+                        Map::balance(kx.clone(), vx.clone(), left.clone(), Arc::new(r))
+                    }),
+                    Equal   => Some(Map::glue(left.clone(), right.clone()))
+                }
+            }
+        }
+    }
+
+    /// Get a map of the first `n` pairs of this map.
+    pub fn take(&self, n: uint) -> Map<K, V> {
+        if n >= self.len() { return self.clone() }
+
+        match *self {
+            Tip => Tip,
+            Bin { key: ref kx, value: ref vx, ref left, ref right, .. } => {
+                match n.cmp(&left.len()) {
+                    Less    => left.take(n),
+                    Greater => Map::link(kx.clone(), vx.clone(), left.clone(),
+                                         Arc::new(right.take(n - left.len() - 1))),
+                    Equal   => (**left).clone()
+                }
+            }
+        }
+    }
+
+    /// Get a map of all but the first `n` pairs of this map.
+    pub fn drop(&self, n: uint) -> Map<K, V> {
+        if n == 0 { return self.clone() }
+
+        match *self {
+            Tip => Tip,
+            Bin { key: ref kx, value: ref vx, ref left, ref right, .. } => {
+                match n.cmp(&left.len()) {
+                    Less    => Map::link(kx.clone(), vx.clone(),
+                                         Arc::new(left.drop(n)), right.clone()),
+                    Greater => right.drop(n - left.len() - 1),
+                    Equal   => right.insert_min(kx.clone(), vx.clone())
+                }
+            }
+        }
+    }
+
+    /// Split a map at an index, into maps of the first `n` pairs and the rest.
+    ///
+    /// This is equivalent to `(map.take(n), map.drop(n))`, but only walks the
+    /// tree once.
+    pub fn split_at(&self, n: uint) -> (Map<K, V>, Map<K, V>) {
+        if n >= self.len() { return (self.clone(), Tip) }
+        if n == 0 { return (Tip, self.clone()) }
+
+        match *self {
+            Tip => (Tip, Tip),
+            Bin { key: ref kx, value: ref vx, ref left, ref right, .. } => {
+                match n.cmp(&left.len()) {
+                    Less    => {
+                        let (lt, gt) = left.split_at(n);
+                        (lt, Map::link(kx.clone(), vx.clone(), Arc::new(gt), right.clone()))
+                    },
+                    Greater => {
+                        let (lt, gt) = right.split_at(n - left.len() - 1);
+                        (Map::link(kx.clone(), vx.clone(), left.clone(), Arc::new(lt)), gt)
+                    },
+                    Equal   => ((**left).clone(), right.insert_min(kx.clone(), vx.clone()))
                 }
             }
         }
     }
 }
 
-// Iterators
-// impl<K: Send + Sync, V: Send + Sync> Map<K, V> {
-//     /// Get a breadth-first iterator over the items in a map.
-//     pub fn bfs_iter(map: Arc<Map<K, V>>) -> BfsItems<K, V> {
-//         let mut queue = collections::RingBuf::new();
-//         queue.push(map);
-//         BfsItems {
-//             queue: queue
-//         }
-//     }
-//
-//     /// Get an inorder iterator over the items in a map.
-//     pub fn inorder_iter(&self) -> OrderItems<(Arc<K>, Arc<V>)> {
-//         match *self {
-//             Tip => {
-//                 let iter: Empty<(Arc<K>, Arc<V>)> = Empty;
-//                 OrderItems(box iter as Box<Iterator<(Arc<K>, Arc<V>)>>)
-//             },
-//             Bin { ref left, ref right, ref value, ref key, .. } => {
-//                 OrderItems(box left.preorder_iter()
-//                     .chain(Some((key.clone(), value.clone())).into_iter())
-//                     .chain(right.preorder_iter()) as Box<Iterator<(Arc<K>, Arc<V>)>>)
-//             }
-//         }
-//     }
-//
-//     /// Get a postorder iterator over the items in a map.
-//     pub fn preorder_iter(&self) -> OrderItems<(Arc<K>, Arc<V>)> {
-//         match *self {
-//             Tip => {
-//                 let iter: Empty<(Arc<K>, Arc<V>)> = Empty;
-//                 OrderItems(box iter as Box<Iterator<(Arc<K>, Arc<V>)>>)
-//             },
-//             Bin { ref left, ref right, ref value, ref key, .. } => {
-//                 OrderItems(box Some((key.clone(), value.clone())).into_iter()
-//                     .chain(left.preorder_iter())
-//                     .chain(right.preorder_iter()) as Box<Iterator<(Arc<K>, Arc<V>)>>)
-//             }
-//         }
-//     }
-//
-//     /// Get a postorder_iterator iterator over the items in a map.
-//     pub fn postorder_iter(&self) -> OrderItems<(Arc<K>, Arc<V>)> {
-//         match *self {
-//             Tip => {
-//                 let iter: Empty<(Arc<K>, Arc<V>)> = Empty;
-//                 OrderItems(box iter as Box<Iterator<(Arc<K>, Arc<V>)>>)
-//             },
-//             Bin { ref left, ref right, ref value, ref key, .. } => {
-//                 OrderItems(box left.preorder_iter()
-//                     .chain(right.preorder_iter())
-//                     .chain(Some((key.clone(), value.clone())).into_iter()) as Box<Iterator<(Arc<K>, Arc<V>)>>)
-//             }
-//         }
-//     }
-//
-// }
-//
-// /// A breadth-first iterator over the pairs of a map.
-// pub struct BfsItems<K, V> {
-//     queue: collections::RingBuf<Arc<Map<K, V>>>
-// }
-//
-// impl<K: Send + Sync, V: Send + Sync> Iterator<(Arc<K>, Arc<V>)> for BfsItems<K, V> {
-//     fn next(&mut self) -> Option<(Arc<K>, Arc<V>)> {
-//         match self.queue.pop_front() {
-//             Some(next) => {
-//                 match *next {
-//                     Tip => self.next(),
-//                     Bin { ref left, ref right, ref key, ref value, .. } => {
-//                         self.queue.push(left.clone());
-//                         self.queue.push(right.clone());
-//                         Some((key.clone(), value.clone()))
-//                     }
-//                 }
-//             },
-//             None => None
-//         }
-//     }
-// }
-//
-// /// An iterator
-// pub struct OrderItems<V>(Box<Iterator<V> + 'static>);
-//
-// impl<V> Iterator<V> for OrderItems<V> {
-//     fn next(&mut self) -> Option<V> {
-//         let OrderItems(ref mut iter) = *self;
-//         iter.next()
-//     }
-// }
-//
-// /// An empty iterator
-// pub struct Empty<V>;
-//
-// impl<T> Iterator<T> for Empty<T> {
-//     fn next(&mut self) -> Option<T> { None }
-// }
+// Ranges
+impl<K: Ord + Send + Sync, V: Send + Sync> Map<K, V> {
+    /// Get an inorder iterator over the pairs whose keys lie between `min`
+    /// and `max`.
+    ///
+    /// Only the branches which can contain keys in the range are visited, and
+    /// like `inorder_iter` the iterator is double-ended and knows its exact
+    /// length. If `min` is above `max` the iterator is empty.
+    pub fn range<'a, Sized? Q>(&'a self, min: Bound<&Q>, max: Bound<&Q>) -> InorderItems<'a, K, V>
+            where Q: Ord + BorrowFrom<K> {
+        let outside = self.count_below(&min) + self.count_above(&max);
+
+        let mut iter = InorderItems {
+            front: Vec::new(),
+            back: Vec::new(),
+            remaining: if outside < self.len() { self.len() - outside } else { 0 }
+        };
+        iter.push_left_above(self, &min);
+        iter.push_right_below(self, &max);
+        iter
+    }
+
+    /// Get an inorder iterator over the pairs whose keys are greater than or
+    /// equal to `min`.
+    #[inline]
+    pub fn range_from<'a, Sized? Q>(&'a self, min: &Q) -> InorderItems<'a, K, V>
+            where Q: Ord + BorrowFrom<K> {
+        self.range(Included(min), Unbounded)
+    }
+
+    /// Get an inorder iterator over the pairs whose keys are less than `max`.
+    #[inline]
+    pub fn range_to<'a, Sized? Q>(&'a self, max: &Q) -> InorderItems<'a, K, V>
+            where Q: Ord + BorrowFrom<K> {
+        self.range(Unbounded, Excluded(max))
+    }
+
+    // How many keys in the map are below the lower bound `min`.
+    fn count_below<Sized? Q>(&self, min: &Bound<&Q>) -> uint where Q: Ord + BorrowFrom<K> {
+        let mut node = self;
+        let mut count = 0;
+
+        loop {
+            match *node {
+                Tip => return count,
+                Bin { ref key, ref left, ref right, .. } => {
+                    if above_lower(&**key, min) {
+                        node = &**left;
+                    } else {
+                        count += left.len() + 1;
+                        node = &**right;
+                    }
+                }
+            }
+        }
+    }
+
+    // How many keys in the map are above the upper bound `max`.
+    fn count_above<Sized? Q>(&self, max: &Bound<&Q>) -> uint where Q: Ord + BorrowFrom<K> {
+        let mut node = self;
+        let mut count = 0;
+
+        loop {
+            match *node {
+                Tip => return count,
+                Bin { ref key, ref left, ref right, .. } => {
+                    if below_upper(&**key, max) {
+                        node = &**right;
+                    } else {
+                        count += right.len() + 1;
+                        node = &**left;
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Does this key satisfy the lower bound of a range?
+fn above_lower<K, Sized? Q>(key: &K, min: &Bound<&Q>) -> bool where Q: Ord + BorrowFrom<K> {
+    let key: &Q = BorrowFrom::borrow_from(key);
+    match *min {
+        Included(min) => key.cmp(min) != Less,
+        Excluded(min) => key.cmp(min) == Greater,
+        Unbounded => true
+    }
+}
+
+// Does this key satisfy the upper bound of a range?
+fn below_upper<K, Sized? Q>(key: &K, max: &Bound<&Q>) -> bool where Q: Ord + BorrowFrom<K> {
+    let key: &Q = BorrowFrom::borrow_from(key);
+    match *max {
+        Included(max) => key.cmp(max) != Greater,
+        Excluded(max) => key.cmp(max) == Less,
+        Unbounded => true
+    }
+}
+
+// Comparisons
 //
+// Maps are compared by their inorder contents, so maps with the same pairs
+// are equal even if their trees have different shapes. Subtrees shared by
+// the two maps are skipped without comparing their pairs.
+impl<K: PartialEq + Send + Sync, V: PartialEq + Send + Sync> PartialEq for Map<K, V> {
+    fn eq(&self, other: &Map<K, V>) -> bool {
+        if self.len() != other.len() { return false }
+
+        let mut a = self.iter();
+        let mut b = other.iter();
+
+        loop {
+            a.skip_shared(&mut b);
+
+            match (a.next(), b.next()) {
+                (Some(x), Some(y)) => if x != y { return false },
+                _ => return true
+            }
+        }
+    }
+}
+
+impl<K: Eq + Send + Sync, V: Eq + Send + Sync> Eq for Map<K, V> {}
+
+impl<K: PartialOrd + Send + Sync, V: PartialOrd + Send + Sync> PartialOrd for Map<K, V> {
+    fn partial_cmp(&self, other: &Map<K, V>) -> Option<Ordering> {
+        let mut a = self.iter();
+        let mut b = other.iter();
+
+        loop {
+            a.skip_shared(&mut b);
+
+            match (a.next(), b.next()) {
+                (None, None) => return Some(Equal),
+                (None, _) => return Some(Less),
+                (_, None) => return Some(Greater),
+                (Some(x), Some(y)) => match x.partial_cmp(&y) {
+                    Some(Equal) => {},
+                    order => return order
+                }
+            }
+        }
+    }
+}
+
+impl<K: Ord + Send + Sync, V: Ord + Send + Sync> Ord for Map<K, V> {
+    fn cmp(&self, other: &Map<K, V>) -> Ordering {
+        let mut a = self.iter();
+        let mut b = other.iter();
+
+        loop {
+            a.skip_shared(&mut b);
+
+            match (a.next(), b.next()) {
+                (None, None) => return Equal,
+                (None, _) => return Less,
+                (_, None) => return Greater,
+                (Some(x), Some(y)) => match x.cmp(&y) {
+                    Equal => {},
+                    order => return order
+                }
+            }
+        }
+    }
+}
+
+impl<S: Writer, K: Hash<S> + Send + Sync, V: Hash<S> + Send + Sync> Hash<S> for Map<K, V> {
+    fn hash(&self, state: &mut S) {
+        self.len().hash(state);
+        for (key, value) in self.iter() {
+            key.hash(state);
+            value.hash(state);
+        }
+    }
+}
+
+impl<K: fmt::Show + Send + Sync, V: fmt::Show + Send + Sync> fmt::Show for Map<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{{"));
+
+        for (i, (key, value)) in self.iter().enumerate() {
+            if i != 0 { try!(write!(f, ", ")); }
+            try!(write!(f, "{}: {}", *key, *value));
+        }
+
+        write!(f, "}}")
+    }
+}
+
+// Iterators
+impl<K: Send + Sync, V: Send + Sync> Map<K, V> {
+    /// Get an iterator over the pairs of a map, in ascending order of keys.
+    ///
+    /// This is the same as `inorder_iter`.
+    #[inline]
+    pub fn iter<'a>(&'a self) -> InorderItems<'a, K, V> { self.inorder_iter() }
+
+    /// Get an inorder iterator over the pairs of a map.
+    ///
+    /// Pairs are yielded in ascending order of their keys. The iterator is
+    /// double-ended and knows its exact length, so `.rev()` and `.len()` are
+    /// both cheap.
+    pub fn inorder_iter<'a>(&'a self) -> InorderItems<'a, K, V> {
+        let mut iter = InorderItems {
+            front: Vec::new(),
+            back: Vec::new(),
+            remaining: self.len()
+        };
+        iter.push_left(self);
+        iter.push_right(self);
+        iter
+    }
+
+    /// Get a preorder iterator over the pairs of a map.
+    ///
+    /// Each node is yielded before the nodes in its left and right branches.
+    pub fn preorder_iter<'a>(&'a self) -> PreorderItems<'a, K, V> {
+        let mut stack = Vec::new();
+        if self.len() != 0 { stack.push(self) }
+
+        PreorderItems {
+            stack: stack,
+            remaining: self.len()
+        }
+    }
+
+    /// Get a postorder iterator over the pairs of a map.
+    ///
+    /// Each node is yielded after the nodes in its left and right branches.
+    pub fn postorder_iter<'a>(&'a self) -> PostorderItems<'a, K, V> {
+        let mut stack = Vec::new();
+        if self.len() != 0 { stack.push((self, false)) }
+
+        PostorderItems {
+            stack: stack,
+            remaining: self.len()
+        }
+    }
+
+    /// Get a breadth-first iterator over the pairs of a map.
+    ///
+    /// Nodes are yielded level by level, starting at the root.
+    pub fn bfs_iter<'a>(&'a self) -> BfsItems<'a, K, V> {
+        let mut queue = RingBuf::new();
+        if self.len() != 0 { queue.push_back(self) }
+
+        BfsItems {
+            queue: queue,
+            remaining: self.len()
+        }
+    }
+}
+
+/// A traversal over the pairs of a map, in some order.
+///
+/// All of the map iterators implement this, which is what allows them
+/// to be adapted into iterators over owned `Arc` pairs using `Arcs`.
+pub trait Traversal<'a, K, V> {
+    /// Get the next pair in the traversal.
+    fn next_pair(&mut self) -> Option<(&'a Arc<K>, &'a Arc<V>)>;
+
+    /// How many pairs are left in the traversal.
+    fn remaining(&self) -> uint;
+}
+
+/// An iterator over the pairs of a map as cloned `Arc`s.
+///
+/// Obtained by calling `arcs` on any of the map iterators.
+pub struct Arcs<I> {
+    iter: I
+}
+
+impl<I> Arcs<I> {
+    /// Adapt a traversal to yield cloned `Arc` pairs.
+    ///
+    /// The map iterators provide this as their `arcs` method. This is for
+    /// traversals of other structures.
+    #[inline]
+    pub fn new(iter: I) -> Arcs<I> { Arcs { iter: iter } }
+}
+
+impl<'a, K: Send + Sync, V: Send + Sync, I: Traversal<'a, K, V>> Iterator<(Arc<K>, Arc<V>)> for Arcs<I> {
+    fn next(&mut self) -> Option<(Arc<K>, Arc<V>)> {
+        self.iter.next_pair().map(|(key, value)| (key.clone(), value.clone()))
+    }
+
+    fn size_hint(&self) -> (uint, Option<uint>) {
+        let remaining = self.iter.remaining();
+        (remaining, Some(remaining))
+    }
+}
+
+// Implement Iterator over borrowed pairs and the `arcs` adaptor for a
+// Traversal.
+macro_rules! traversal_iterator(
+    ($name:ident) => (
+        impl<'a, K: Send + Sync, V: Send + Sync> $name<'a, K, V> {
+            /// Adapt this iterator to yield cloned `Arc` pairs instead of references.
+            #[inline]
+            pub fn arcs(self) -> Arcs<$name<'a, K, V>> { Arcs { iter: self } }
+        }
+
+        impl<'a, K: Send + Sync, V: Send + Sync> Iterator<(&'a K, &'a V)> for $name<'a, K, V> {
+            fn next(&mut self) -> Option<(&'a K, &'a V)> {
+                self.next_pair().map(|(key, value)| (&**key, &**value))
+            }
+
+            fn size_hint(&self) -> (uint, Option<uint>) {
+                (self.remaining, Some(self.remaining))
+            }
+        }
+    )
+)
+
+/// An inorder iterator over the pairs of a map.
+///
+/// This iterator is double-ended, so it can also be used to walk a map
+/// from its largest key downwards.
+pub struct InorderItems<'a, K: 'a, V: 'a> {
+    // Nodes whose pairs have yet to be yielded from the front, but whose
+    // left branches have already been pushed.
+    front: Vec<&'a Map<K, V>>,
+
+    // Nodes whose pairs have yet to be yielded from the back, but whose
+    // right branches have already been pushed.
+    back: Vec<&'a Map<K, V>>,
+
+    // The two stacks can overlap, so this is what stops the ends from
+    // crossing each other.
+    remaining: uint
+}
+
+impl<'a, K: Send + Sync, V: Send + Sync> InorderItems<'a, K, V> {
+    // Push the left spine of this node onto the front stack.
+    fn push_left(&mut self, mut node: &'a Map<K, V>) {
+        loop {
+            match *node {
+                Tip => break,
+                Bin { ref left, .. } => {
+                    self.front.push(node);
+                    node = &**left;
+                }
+            }
+        }
+    }
+
+    // Skip the pairs at the front of both iterators for as long as they come
+    // from the same shared subtree, since they must be equal. Both iterators
+    // must be at the same position.
+    fn skip_shared(&mut self, other: &mut InorderItems<'a, K, V>) {
+        loop {
+            let node = match (self.front.last(), other.front.last()) {
+                (Some(&a), Some(&b)) if a as *const Map<K, V> == b as *const Map<K, V> => a,
+                _ => return
+            };
+
+            // This node and its right branch come next in both iterators.
+            let skipped = match *node {
+                Bin { ref right, .. } => right.len() + 1,
+                Tip => 0
+            };
+
+            self.front.pop();
+            other.front.pop();
+            self.remaining -= skipped;
+            other.remaining -= skipped;
+        }
+    }
+
+    // Push the right spine of this node onto the back stack.
+    fn push_right(&mut self, mut node: &'a Map<K, V>) {
+        loop {
+            match *node {
+                Tip => break,
+                Bin { ref right, .. } => {
+                    self.back.push(node);
+                    node = &**right;
+                }
+            }
+        }
+    }
+}
+
+impl<'a, K: Ord + Send + Sync, V: Send + Sync> InorderItems<'a, K, V> {
+    // Push the nodes on the path to the smallest key satisfying `min` onto
+    // the front stack, skipping branches which are entirely below it.
+    fn push_left_above<Sized? Q>(&mut self, mut node: &'a Map<K, V>, min: &Bound<&Q>)
+            where Q: Ord + BorrowFrom<K> {
+        loop {
+            match *node {
+                Tip => break,
+                Bin { ref key, ref left, ref right, .. } => {
+                    if above_lower(&**key, min) {
+                        self.front.push(node);
+                        node = &**left;
+                    } else {
+                        node = &**right;
+                    }
+                }
+            }
+        }
+    }
+
+    // Push the nodes on the path to the largest key satisfying `max` onto
+    // the back stack, skipping branches which are entirely above it.
+    fn push_right_below<Sized? Q>(&mut self, mut node: &'a Map<K, V>, max: &Bound<&Q>)
+            where Q: Ord + BorrowFrom<K> {
+        loop {
+            match *node {
+                Tip => break,
+                Bin { ref key, ref left, ref right, .. } => {
+                    if below_upper(&**key, max) {
+                        self.back.push(node);
+                        node = &**right;
+                    } else {
+                        node = &**left;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A traversal which can also be walked from the back.
+pub trait DoubleEndedTraversal<'a, K, V>: Traversal<'a, K, V> {
+    /// Get the next pair from the back of the traversal.
+    fn next_back_pair(&mut self) -> Option<(&'a Arc<K>, &'a Arc<V>)>;
+}
+
+impl<'a, K: Send + Sync, V: Send + Sync> Traversal<'a, K, V> for InorderItems<'a, K, V> {
+    fn next_pair(&mut self) -> Option<(&'a Arc<K>, &'a Arc<V>)> {
+        if self.remaining == 0 { return None }
+
+        match self.front.pop() {
+            Some(&Bin { ref key, ref value, ref right, .. }) => {
+                self.push_left(&**right);
+                self.remaining -= 1;
+                Some((key, value))
+            },
+            Some(&Tip) | None => None
+        }
+    }
+
+    #[inline]
+    fn remaining(&self) -> uint { self.remaining }
+}
+
+impl<'a, K: Send + Sync, V: Send + Sync> DoubleEndedTraversal<'a, K, V> for InorderItems<'a, K, V> {
+    fn next_back_pair(&mut self) -> Option<(&'a Arc<K>, &'a Arc<V>)> {
+        if self.remaining == 0 { return None }
+
+        match self.back.pop() {
+            Some(&Bin { ref key, ref value, ref left, .. }) => {
+                self.push_right(&**left);
+                self.remaining -= 1;
+                Some((key, value))
+            },
+            Some(&Tip) | None => None
+        }
+    }
+}
+
+traversal_iterator!(InorderItems)
+
+impl<'a, K: Send + Sync, V: Send + Sync> DoubleEndedIterator<(&'a K, &'a V)> for InorderItems<'a, K, V> {
+    fn next_back(&mut self) -> Option<(&'a K, &'a V)> {
+        self.next_back_pair().map(|(key, value)| (&**key, &**value))
+    }
+}
+
+impl<'a, K: Send + Sync, V: Send + Sync> ExactSize<(&'a K, &'a V)> for InorderItems<'a, K, V> {}
+
+impl<'a, K: Send + Sync, V: Send + Sync, I: DoubleEndedTraversal<'a, K, V>> DoubleEndedIterator<(Arc<K>, Arc<V>)> for Arcs<I> {
+    fn next_back(&mut self) -> Option<(Arc<K>, Arc<V>)> {
+        self.iter.next_back_pair().map(|(key, value)| (key.clone(), value.clone()))
+    }
+}
+
+impl<'a, K: Send + Sync, V: Send + Sync, I: DoubleEndedTraversal<'a, K, V>> ExactSize<(Arc<K>, Arc<V>)> for Arcs<I> {}
+
+/// A preorder iterator over the pairs of a map.
+pub struct PreorderItems<'a, K: 'a, V: 'a> {
+    // Non-empty nodes waiting to be yielded.
+    stack: Vec<&'a Map<K, V>>,
+    remaining: uint
+}
+
+impl<'a, K: Send + Sync, V: Send + Sync> Traversal<'a, K, V> for PreorderItems<'a, K, V> {
+    fn next_pair(&mut self) -> Option<(&'a Arc<K>, &'a Arc<V>)> {
+        match self.stack.pop() {
+            Some(&Bin { ref key, ref value, ref left, ref right, .. }) => {
+                // Push right first so that the left branch is visited first.
+                if right.len() != 0 { self.stack.push(&**right) }
+                if left.len() != 0 { self.stack.push(&**left) }
+                self.remaining -= 1;
+                Some((key, value))
+            },
+            Some(&Tip) | None => None
+        }
+    }
+
+    #[inline]
+    fn remaining(&self) -> uint { self.remaining }
+}
+
+traversal_iterator!(PreorderItems)
+
+/// A postorder iterator over the pairs of a map.
+pub struct PostorderItems<'a, K: 'a, V: 'a> {
+    // Non-empty nodes waiting to be yielded, paired with whether or not
+    // their branches have already been pushed.
+    stack: Vec<(&'a Map<K, V>, bool)>,
+    remaining: uint
+}
+
+impl<'a, K: Send + Sync, V: Send + Sync> Traversal<'a, K, V> for PostorderItems<'a, K, V> {
+    fn next_pair(&mut self) -> Option<(&'a Arc<K>, &'a Arc<V>)> {
+        loop {
+            match self.stack.pop() {
+                None => return None,
+                Some((node, expanded)) => match *node {
+                    Tip => {},
+                    Bin { ref key, ref value, ref left, ref right, .. } => {
+                        if expanded {
+                            self.remaining -= 1;
+                            return Some((key, value))
+                        }
+
+                        // Revisit this node once both branches are done.
+                        self.stack.push((node, true));
+                        if right.len() != 0 { self.stack.push((&**right, false)) }
+                        if left.len() != 0 { self.stack.push((&**left, false)) }
+                    }
+                }
+            }
+        }
+    }
+
+    #[inline]
+    fn remaining(&self) -> uint { self.remaining }
+}
+
+traversal_iterator!(PostorderItems)
+
+/// A breadth-first iterator over the pairs of a map.
+pub struct BfsItems<'a, K: 'a, V: 'a> {
+    // Non-empty nodes waiting to be yielded.
+    queue: RingBuf<&'a Map<K, V>>,
+    remaining: uint
+}
+
+impl<'a, K: Send + Sync, V: Send + Sync> Traversal<'a, K, V> for BfsItems<'a, K, V> {
+    fn next_pair(&mut self) -> Option<(&'a Arc<K>, &'a Arc<V>)> {
+        match self.queue.pop_front() {
+            Some(&Bin { ref key, ref value, ref left, ref right, .. }) => {
+                if left.len() != 0 { self.queue.push_back(&**left) }
+                if right.len() != 0 { self.queue.push_back(&**right) }
+                self.remaining -= 1;
+                Some((key, value))
+            },
+            Some(&Tip) | None => None
+        }
+    }
+
+    #[inline]
+    fn remaining(&self) -> uint { self.remaining }
+}
+
+traversal_iterator!(BfsItems)
@@ -0,0 +1,364 @@
+use std::sync::Arc;
+use std::default::Default;
+use std::borrow::BorrowFrom;
+use std::fmt;
+use std::hash::{Hash, Writer};
+use std::iter::{ExactSize, FromIterator};
+
+use map::{Map, Bin, Tip, Bound, InorderItems, InvariantError};
+
+/// A persistent, functional set, implemented as a size balanced binary
+/// search tree.
+///
+/// A set is a map from its keys to `()`, and shares all of the balancing,
+/// splitting and joining of `Map`. Every key in a set shares a single `Arc`
+/// for its value, so no value is allocated per key, although every node
+/// still holds a pointer to it.
+pub struct Set<K> {
+    map: Map<K, ()>
+}
+
+impl<K: Send + Sync> Set<K> {
+    /// An empty set.
+    #[inline]
+    pub fn new() -> Set<K> { Set { map: Map::new() } }
+
+    /// Create a set with one key.
+    #[inline]
+    pub fn singleton(key: K) -> Set<K> { Set { map: Map::singleton(key, ()) } }
+
+    /// How many keys are in the set.
+    #[inline]
+    pub fn len(&self) -> uint { self.map.len() }
+
+    /// Is the set empty?
+    #[inline]
+    pub fn is_empty(&self) -> bool { self.map.len() == 0 }
+
+    /// Get an inorder iterator over the keys of the set.
+    #[inline]
+    pub fn iter<'a>(&'a self) -> SetItems<'a, K> { SetItems { iter: self.map.iter() } }
+
+    /// Get the map backing this set.
+    #[inline]
+    pub fn as_map(&self) -> &Map<K, ()> { &self.map }
+
+    // The value shared by every key of the set, which new keys reuse rather
+    // than allocating their own.
+    fn unit(&self) -> Arc<()> {
+        match self.map {
+            Bin { ref value, .. } => value.clone(),
+            Tip => Arc::new(())
+        }
+    }
+}
+
+impl<K: Send + Sync + Ord> Set<K> {
+    /// Is this key a member of the set?
+    #[inline]
+    pub fn contains<Sized? Q>(&self, key: &Q) -> bool where Q: Ord + BorrowFrom<K> {
+        self.map.contains(key)
+    }
+
+    /// Insert a key into the set.
+    ///
+    /// If the key is already present, the key in the set is replaced.
+    pub fn insert(&self, key: Arc<K>) -> Set<K> {
+        Set { map: self.map.insert(key, self.unit()) }
+    }
+
+    /// Delete a key from the set.
+    ///
+    /// If the key is not a member of the set, the original set is returned.
+    pub fn delete<Sized? Q>(&self, key: &Q) -> Set<K> where Q: Ord + BorrowFrom<K> {
+        Set { map: self.map.delete(key) }
+    }
+
+    /// Remove a key from the set, returning the removed key and the new set.
+    ///
+    /// Returns None if the key is not a member of the set.
+    pub fn remove<Sized? Q>(&self, key: &Q) -> Option<(Arc<K>, Set<K>)>
+            where Q: Ord + BorrowFrom<K> {
+        self.map.remove(key).map(|((key, _), map)| (key, Set { map: map }))
+    }
+
+    /// Find the smallest key in the set.
+    #[inline]
+    pub fn min(&self) -> Option<Arc<K>> { self.map.min().map(|(key, _)| key) }
+
+    /// Find the largest key in the set.
+    #[inline]
+    pub fn max(&self) -> Option<Arc<K>> { self.map.max().map(|(key, _)| key) }
+
+    /// Remove the smallest key from the set, returning it and the new set.
+    pub fn pop_min(&self) -> Option<(Arc<K>, Set<K>)> {
+        self.map.pop_min().map(|((key, _), map)| (key, Set { map: map }))
+    }
+
+    /// Remove the largest key from the set, returning it and the new set.
+    pub fn pop_max(&self) -> Option<(Arc<K>, Set<K>)> {
+        self.map.pop_max().map(|((key, _), map)| (key, Set { map: map }))
+    }
+
+    /// Find the largest key which is less than `key`.
+    pub fn lookup_lt<'a, Sized? Q>(&'a self, key: &Q) -> Option<&'a K>
+            where Q: Ord + BorrowFrom<K> {
+        self.map.lookup_lt(key).map(|(key, _)| key)
+    }
+
+    /// Find the smallest key which is greater than `key`.
+    pub fn lookup_gt<'a, Sized? Q>(&'a self, key: &Q) -> Option<&'a K>
+            where Q: Ord + BorrowFrom<K> {
+        self.map.lookup_gt(key).map(|(key, _)| key)
+    }
+
+    /// Find the largest key which is less than or equal to `key`.
+    pub fn lookup_le<'a, Sized? Q>(&'a self, key: &Q) -> Option<&'a K>
+            where Q: Ord + BorrowFrom<K> {
+        self.map.lookup_le(key).map(|(key, _)| key)
+    }
+
+    /// Find the smallest key which is greater than or equal to `key`.
+    pub fn lookup_ge<'a, Sized? Q>(&'a self, key: &Q) -> Option<&'a K>
+            where Q: Ord + BorrowFrom<K> {
+        self.map.lookup_ge(key).map(|(key, _)| key)
+    }
+
+    /// Check that the set's tree is ordered, sized and balanced correctly.
+    ///
+    /// See `Map::validate`.
+    #[inline]
+    pub fn validate(&self) -> Result<(), InvariantError> { self.map.validate() }
+}
+
+// Splitting and joining
+impl<K: Send + Sync + Ord> Set<K> {
+    /// Split a set into the keys smaller than `key`, whether `key` is a
+    /// member, and the keys larger than `key`.
+    pub fn split(&self, key: &K) -> (Set<K>, bool, Set<K>) {
+        let (lt, found, gt) = self.map.split(key);
+        (Set { map: lt }, found.is_some(), Set { map: gt })
+    }
+
+    /// Concatenate two sets, where all keys in `left` are smaller than all
+    /// keys in `right`.
+    ///
+    /// Otherwise the resulting set is not ordered.
+    pub fn merge(left: &Set<K>, right: &Set<K>) -> Set<K> {
+        Set { map: Map::merge(Arc::new(left.map.clone()), Arc::new(right.map.clone())) }
+    }
+}
+
+// Set operations
+impl<K: Send + Sync + Ord> Set<K> {
+    /// The keys which are in either set.
+    #[inline]
+    pub fn union(&self, other: &Set<K>) -> Set<K> { Set { map: self.map.union(&other.map) } }
+
+    /// The keys which are in both sets.
+    #[inline]
+    pub fn intersection(&self, other: &Set<K>) -> Set<K> {
+        Set { map: self.map.intersection(&other.map) }
+    }
+
+    /// The keys of `self` which are not in `other`.
+    #[inline]
+    pub fn difference(&self, other: &Set<K>) -> Set<K> {
+        Set { map: self.map.difference(&other.map) }
+    }
+
+    /// The keys which are in exactly one of the two sets.
+    #[inline]
+    pub fn symmetric_difference(&self, other: &Set<K>) -> Set<K> {
+        Set { map: self.map.symmetric_difference(&other.map) }
+    }
+
+    /// Are all the keys of this set also in `other`?
+    #[inline]
+    pub fn is_subset(&self, other: &Set<K>) -> bool { self.map.is_subset(&other.map) }
+
+    /// Are all the keys of `other` also in this set?
+    #[inline]
+    pub fn is_superset(&self, other: &Set<K>) -> bool { other.map.is_subset(&self.map) }
+
+    /// Do these sets have no keys in common?
+    #[inline]
+    pub fn is_disjoint(&self, other: &Set<K>) -> bool { self.map.is_disjoint(&other.map) }
+
+    /// The keys of the set which satisfy a predicate.
+    pub fn filter(&self, predicate: |&K| -> bool) -> Set<K> {
+        Set { map: self.map.filter(|key, _| predicate(key)) }
+    }
+
+    /// Split the set into the keys which satisfy a predicate and those which
+    /// do not.
+    pub fn partition(&self, predicate: |&K| -> bool) -> (Set<K>, Set<K>) {
+        let (yes, no) = self.map.partition(|key, _| predicate(key));
+        (Set { map: yes }, Set { map: no })
+    }
+}
+
+// Indexing
+//
+// Indices are positions in the inorder traversal of the set, as for Map.
+impl<K: Send + Sync + Ord> Set<K> {
+    /// Find the index of a key in the set.
+    #[inline]
+    pub fn index_of<Sized? Q>(&self, key: &Q) -> Option<uint> where Q: Ord + BorrowFrom<K> {
+        self.map.index_of(key)
+    }
+
+    /// Get the key at an index in the set.
+    #[inline]
+    pub fn elem_at<'a>(&'a self, index: uint) -> Option<&'a K> {
+        self.map.elem_at(index).map(|(key, _)| key)
+    }
+
+    /// Delete the key at an index in the set.
+    ///
+    /// Returns None if the index is out of bounds.
+    pub fn delete_at(&self, index: uint) -> Option<Set<K>> {
+        self.map.delete_at(index).map(|map| Set { map: map })
+    }
+
+    /// Get a set of the first `n` keys of this set.
+    #[inline]
+    pub fn take(&self, n: uint) -> Set<K> { Set { map: self.map.take(n) } }
+
+    /// Get a set of all but the first `n` keys of this set.
+    #[inline]
+    pub fn drop(&self, n: uint) -> Set<K> { Set { map: self.map.drop(n) } }
+
+    /// Split a set at an index, into sets of the first `n` keys and the rest.
+    pub fn split_at(&self, n: uint) -> (Set<K>, Set<K>) {
+        let (lt, gt) = self.map.split_at(n);
+        (Set { map: lt }, Set { map: gt })
+    }
+}
+
+// Ranges
+impl<K: Send + Sync + Ord> Set<K> {
+    /// Get an inorder iterator over the keys which lie between `min` and
+    /// `max`.
+    ///
+    /// See `Map::range`.
+    #[inline]
+    pub fn range<'a, Sized? Q>(&'a self, min: Bound<&Q>, max: Bound<&Q>) -> SetItems<'a, K>
+            where Q: Ord + BorrowFrom<K> {
+        SetItems { iter: self.map.range(min, max) }
+    }
+
+    /// Get an inorder iterator over the keys which are greater than or equal
+    /// to `min`.
+    #[inline]
+    pub fn range_from<'a, Sized? Q>(&'a self, min: &Q) -> SetItems<'a, K>
+            where Q: Ord + BorrowFrom<K> {
+        SetItems { iter: self.map.range_from(min) }
+    }
+
+    /// Get an inorder iterator over the keys which are less than `max`.
+    #[inline]
+    pub fn range_to<'a, Sized? Q>(&'a self, max: &Q) -> SetItems<'a, K>
+            where Q: Ord + BorrowFrom<K> {
+        SetItems { iter: self.map.range_to(max) }
+    }
+}
+
+impl<K: Send + Sync> Clone for Set<K> {
+    #[inline]
+    fn clone(&self) -> Set<K> { Set { map: self.map.clone() } }
+}
+
+impl<K: Send + Sync> Default for Set<K> {
+    #[inline]
+    fn default() -> Set<K> { Set::new() }
+}
+
+// Comparisons
+//
+// Sets are compared by their keys in order, like maps.
+impl<K: PartialEq + Send + Sync> PartialEq for Set<K> {
+    #[inline]
+    fn eq(&self, other: &Set<K>) -> bool { self.map == other.map }
+}
+
+impl<K: Eq + Send + Sync> Eq for Set<K> {}
+
+impl<K: PartialOrd + Send + Sync> PartialOrd for Set<K> {
+    #[inline]
+    fn partial_cmp(&self, other: &Set<K>) -> Option<Ordering> { self.map.partial_cmp(&other.map) }
+}
+
+impl<K: Ord + Send + Sync> Ord for Set<K> {
+    #[inline]
+    fn cmp(&self, other: &Set<K>) -> Ordering { self.map.cmp(&other.map) }
+}
+
+impl<S: Writer, K: Hash<S> + Send + Sync> Hash<S> for Set<K> {
+    #[inline]
+    fn hash(&self, state: &mut S) { self.map.hash(state) }
+}
+
+impl<K: fmt::Show + Send + Sync> fmt::Show for Set<K> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{{"));
+
+        for (i, key) in self.iter().enumerate() {
+            if i != 0 { try!(write!(f, ", ")); }
+            try!(write!(f, "{}", *key));
+        }
+
+        write!(f, "}}")
+    }
+}
+
+impl<K: Send + Sync + Ord> FromIterator<K> for Set<K> {
+    /// Build a set from an iterator of keys, in O(n log n) time.
+    ///
+    /// The keys are sorted first and the set is built from them in linear
+    /// time, with every key sharing one value.
+    fn from_iter<I: Iterator<K>>(iter: I) -> Set<K> {
+        let mut keys: Vec<K> = iter.collect();
+        keys.sort();
+        keys.dedup();
+
+        let unit = Arc::new(());
+        let pairs = keys.into_iter().map(|key| (Arc::new(key), unit.clone()));
+        Set { map: Map::from_distinct_ascending_arcs(pairs) }
+    }
+}
+
+impl<K: Send + Sync + Ord> Extend<K> for Set<K> {
+    /// Add the keys of an iterator to the set, replacing keys which are
+    /// already present.
+    ///
+    /// The new keys are built into a set of their own and then combined with
+    /// this one using `union`.
+    fn extend<I: Iterator<K>>(&mut self, iter: I) {
+        let new: Set<K> = iter.collect();
+        *self = new.union(self);
+    }
+}
+
+/// An inorder iterator over the keys of a set.
+///
+/// Like the inorder iterator of a map, this iterator is double-ended and
+/// knows its exact length.
+pub struct SetItems<'a, K: 'a> {
+    iter: InorderItems<'a, K, ()>
+}
+
+impl<'a, K: Send + Sync> Iterator<&'a K> for SetItems<'a, K> {
+    #[inline]
+    fn next(&mut self) -> Option<&'a K> { self.iter.next().map(|(key, _)| key) }
+
+    #[inline]
+    fn size_hint(&self) -> (uint, Option<uint>) { self.iter.size_hint() }
+}
+
+impl<'a, K: Send + Sync> DoubleEndedIterator<&'a K> for SetItems<'a, K> {
+    #[inline]
+    fn next_back(&mut self) -> Option<&'a K> { self.iter.next_back().map(|(key, _)| key) }
+}
+
+impl<'a, K: Send + Sync> ExactSize<&'a K> for SetItems<'a, K> {}
@@ -0,0 +1,315 @@
+use std::sync::Arc;
+use std::default::Default;
+use std::fmt;
+use std::iter::FromIterator;
+
+use map::{Map, InorderItems};
+
+/// A key value store keyed by sequences, implemented as a persistent,
+/// functional trie.
+///
+/// Each node holds the value for the sequence leading to it, if there is
+/// one, and a `Map` from the next element of the sequence to its children.
+/// Every version of a trie shares the nodes and child maps that an
+/// operation did not touch.
+pub struct Trie<K, V> {
+    value: Option<Arc<V>>,
+    children: Map<K, Trie<K, V>>,
+    size: uint
+}
+
+impl<K: Send + Sync, V: Send + Sync> Trie<K, V> {
+    /// An empty trie.
+    #[inline]
+    pub fn new() -> Trie<K, V> {
+        Trie {
+            value: None,
+            children: Map::new(),
+            size: 0
+        }
+    }
+
+    /// How many values are in the trie.
+    #[inline]
+    pub fn len(&self) -> uint { self.size }
+
+    /// Is the trie empty?
+    #[inline]
+    pub fn is_empty(&self) -> bool { self.size == 0 }
+
+    /// Get the value for the empty sequence, at the root of this trie.
+    #[inline]
+    pub fn root(&self) -> Option<&V> { self.value.as_ref().map(|value| &**value) }
+}
+
+impl<K: Send + Sync, V: Send + Sync> Clone for Trie<K, V> {
+    fn clone(&self) -> Trie<K, V> {
+        Trie {
+            value: self.value.clone(),
+            children: self.children.clone(),
+            size: self.size
+        }
+    }
+}
+
+impl<K: Send + Sync, V: Send + Sync> Default for Trie<K, V> {
+    #[inline]
+    fn default() -> Trie<K, V> { Trie::new() }
+}
+
+// Lookup
+impl<K: Ord + Send + Sync, V: Send + Sync> Trie<K, V> {
+    /// Get the value at a key in the trie.
+    #[inline]
+    pub fn get(&self, key: &[K]) -> Option<&V> { self.subtrie(key).and_then(|trie| trie.root()) }
+
+    /// Is this key a member of the trie?
+    #[inline]
+    pub fn contains(&self, key: &[K]) -> bool { self.get(key).is_some() }
+
+    /// Get the trie of all the keys which start with `prefix`, with the
+    /// prefix removed from them.
+    ///
+    /// Returns None if no key starts with `prefix`.
+    pub fn subtrie<'a>(&'a self, prefix: &[K]) -> Option<&'a Trie<K, V>> {
+        let mut node = self;
+
+        for element in prefix.iter() {
+            match node.children.get(element) {
+                Some(child) => node = child,
+                None => return None
+            }
+        }
+
+        Some(node)
+    }
+
+    /// Find the longest prefix of `key` which is a member of the trie.
+    ///
+    /// Returns the length of that prefix and its value, or None if no prefix
+    /// of `key` is a member, including the empty one.
+    pub fn longest_prefix_match<'a>(&'a self, key: &[K]) -> Option<(uint, &'a V)> {
+        let mut node = self;
+        let mut longest = self.root().map(|value| (0, value));
+
+        for (i, element) in key.iter().enumerate() {
+            match node.children.get(element) {
+                Some(child) => node = child,
+                None => break
+            }
+
+            match node.root() {
+                Some(value) => longest = Some((i + 1, value)),
+                None => {}
+            }
+        }
+
+        longest
+    }
+
+    /// Remove a key from the trie, returning its value and the new trie.
+    ///
+    /// Returns None if the key is not a member of the trie.
+    pub fn remove(&self, key: &[K]) -> Option<(Arc<V>, Trie<K, V>)> {
+        if key.is_empty() {
+            return self.value.as_ref().map(|value| {
+                (value.clone(), Trie {
+                    value: None,
+                    children: self.children.clone(),
+                    size: self.size - 1
+                })
+            })
+        }
+
+        let child = match self.children.get(&key[0]) {
+            Some(child) => child,
+            None => return None
+        };
+
+        child.remove(key.slice_from(1)).map(|(value, child)| {
+            // Branches left without any values are removed entirely.
+            let mut replacement = if child.is_empty() { None } else { Some(child) };
+
+            (value, Trie {
+                value: self.value.clone(),
+                children: self.children.update(&key[0], |_| replacement.take()),
+                size: self.size - 1
+            })
+        })
+    }
+
+    /// Delete a key and its value from the trie.
+    ///
+    /// If the key is not a member of the trie, the original trie is returned.
+    pub fn delete(&self, key: &[K]) -> Trie<K, V> {
+        match self.remove(key) {
+            Some((_, trie)) => trie,
+            None => self.clone()
+        }
+    }
+}
+
+impl<K: Ord + Clone + Send + Sync, V: Send + Sync> Trie<K, V> {
+    /// Insert a key value pair into the trie. If the key is already present,
+    /// its value is replaced.
+    pub fn insert(&self, key: &[K], value: Arc<V>) -> Trie<K, V> {
+        if key.is_empty() {
+            return Trie {
+                value: Some(value),
+                children: self.children.clone(),
+                size: if self.value.is_some() { self.size } else { self.size + 1 }
+            }
+        }
+
+        let (children, added) = match self.children.get(&key[0]) {
+            Some(old) => {
+                let child = old.insert(key.slice_from(1), value);
+                let added = child.size - old.size;
+                let mut replacement = Some(child);
+                (self.children.update(&key[0], |_| replacement.take()), added)
+            },
+            None => {
+                let child = Trie::new().insert(key.slice_from(1), value);
+                (self.children.insert(Arc::new(key[0].clone()), Arc::new(child)), 1)
+            }
+        };
+
+        Trie {
+            value: self.value.clone(),
+            children: children,
+            size: self.size + added
+        }
+    }
+
+    /// Get an iterator over the pairs of the trie, in ascending order of keys.
+    ///
+    /// A key comes before all of the longer keys which start with it.
+    #[inline]
+    pub fn iter<'a>(&'a self) -> TrieItems<'a, K, V> { TrieItems::new(Vec::new(), Some(self)) }
+
+    /// Get an iterator over the pairs of the trie whose keys start with
+    /// `prefix`, in ascending order of keys.
+    ///
+    /// The keys yielded include the prefix.
+    pub fn iter_prefix<'a>(&'a self, prefix: &[K]) -> TrieItems<'a, K, V> {
+        TrieItems::new(prefix.to_vec(), self.subtrie(prefix))
+    }
+}
+
+// Comparisons
+//
+// Tries are compared node by node. Tries with the same pairs always have the
+// same shape, since branches without values are removed.
+impl<K: PartialEq + Send + Sync, V: PartialEq + Send + Sync> PartialEq for Trie<K, V> {
+    fn eq(&self, other: &Trie<K, V>) -> bool {
+        self.size == other.size && self.value == other.value && self.children == other.children
+    }
+}
+
+impl<K: Eq + Send + Sync, V: Eq + Send + Sync> Eq for Trie<K, V> {}
+
+impl<K: Ord + Clone + fmt::Show + Send + Sync, V: fmt::Show + Send + Sync> fmt::Show for Trie<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{{"));
+
+        for (i, (key, value)) in self.iter().enumerate() {
+            if i != 0 { try!(write!(f, ", ")); }
+            try!(write!(f, "{}: {}", key, *value));
+        }
+
+        write!(f, "}}")
+    }
+}
+
+impl<K: Ord + Clone + Send + Sync, V: Send + Sync> FromIterator<(Vec<K>, V)> for Trie<K, V> {
+    /// Build a trie from an iterator of pairs. Later values replace earlier
+    /// ones.
+    fn from_iter<I: Iterator<(Vec<K>, V)>>(iter: I) -> Trie<K, V> {
+        let mut trie = Trie::new();
+        trie.extend(iter);
+        trie
+    }
+}
+
+impl<K: Ord + Clone + Send + Sync, V: Send + Sync> Extend<(Vec<K>, V)> for Trie<K, V> {
+    /// Add pairs from an iterator to the trie, replacing the values of keys
+    /// which are already present.
+    fn extend<I: Iterator<(Vec<K>, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            *self = self.insert(key.as_slice(), Arc::new(value));
+        }
+    }
+}
+
+/// An iterator over the pairs of a trie, in ascending order of keys.
+pub struct TrieItems<'a, K: 'a, V: 'a> {
+    // The key of the node being visited.
+    path: Vec<K>,
+
+    // Iterators over the children of each node on the path, the node being
+    // visited last.
+    stack: Vec<InorderItems<'a, K, Trie<K, V>>>,
+
+    // The value of the node being visited, if it has not been yielded yet.
+    pending: Option<&'a V>,
+
+    remaining: uint
+}
+
+impl<'a, K: Clone + Send + Sync, V: Send + Sync> TrieItems<'a, K, V> {
+    // Iterate over the pairs of a subtrie, whose keys start with `path`.
+    fn new(path: Vec<K>, trie: Option<&'a Trie<K, V>>) -> TrieItems<'a, K, V> {
+        match trie {
+            Some(trie) => TrieItems {
+                path: path,
+                stack: vec![trie.children.iter()],
+                pending: trie.root(),
+                remaining: trie.size
+            },
+            None => TrieItems {
+                path: path,
+                stack: Vec::new(),
+                pending: None,
+                remaining: 0
+            }
+        }
+    }
+}
+
+impl<'a, K: Clone + Send + Sync, V: Send + Sync> Iterator<(Vec<K>, &'a V)> for TrieItems<'a, K, V> {
+    fn next(&mut self) -> Option<(Vec<K>, &'a V)> {
+        loop {
+            match self.pending.take() {
+                Some(value) => {
+                    self.remaining -= 1;
+                    return Some((self.path.clone(), value))
+                },
+                None => {}
+            }
+
+            let next = match self.stack.last_mut() {
+                Some(children) => children.next(),
+                None => return None
+            };
+
+            match next {
+                Some((element, child)) => {
+                    self.path.push(element.clone());
+                    self.stack.push(child.children.iter());
+                    self.pending = child.root();
+                },
+                None => {
+                    // The subtrie at the end of the path is finished, so step
+                    // back up to its parent. The first node's path is the
+                    // prefix, which stays.
+                    self.stack.pop();
+                    if !self.stack.is_empty() { self.path.pop(); }
+                }
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (uint, Option<uint>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
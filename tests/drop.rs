@@ -0,0 +1,64 @@
+//! Tests that long persistent structures can be dropped without overflowing
+//! the stack, and that dropping one version leaves the versions it shares
+//! structure with intact.
+
+extern crate adamantium;
+
+use std::sync::Arc;
+
+use adamantium::{Heap, List, Map, Stack};
+use adamantium::list::SharedList;
+
+// Long enough that recursively dropping every cell overflows the stack.
+static LEN: uint = 1_000_000;
+
+fn long_list() -> List<uint> {
+    range(0, LEN).fold(List::new(), |list, i| list.push_front(i))
+}
+
+#[test]
+fn long_list_drops() {
+    drop(long_list());
+}
+
+#[test]
+fn long_stack_drops() {
+    drop(range(0, LEN).fold(Stack::new(), |stack, i| stack.push(i)));
+}
+
+#[test]
+fn long_descending_heap_drops() {
+    // Each push makes the old heap the left branch of the new root.
+    drop(range(0, LEN).rev().fold(Heap::new(), |heap, i| heap.push(Arc::new(i))));
+}
+
+#[test]
+fn large_map_drops() {
+    // Ascending inserts keep rebalancing the right spine of the tree.
+    drop(range(0, LEN).fold(Map::new(), |map, i| map.insert(Arc::new(i), Arc::new(i))));
+}
+
+#[test]
+fn dropping_a_map_keeps_shared_branches() {
+    let map = Map::from_distinct_ascending(range(0, LEN).map(|i| (i, i)));
+    let smaller = map.delete(&0);
+
+    drop(map);
+    assert_eq!(smaller.len(), LEN - 1);
+    assert!(smaller.validate().is_ok());
+    assert_eq!(smaller.get(&(LEN - 1)), Some(&(LEN - 1)));
+}
+
+#[test]
+fn dropping_a_list_keeps_shared_tail() {
+    let list = long_list();
+    let tail = list.tail().unwrap();
+    let longer = tail.cons(LEN);
+
+    drop(list);
+    assert_eq!(tail.len(), LEN - 1);
+
+    drop(longer);
+    assert_eq!(tail.iter().next(), Some(&(LEN - 2)));
+    assert_eq!(tail.len(), LEN - 1);
+}
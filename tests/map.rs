@@ -0,0 +1,87 @@
+//! Tests for the parts of the map API which the randomized tests in
+//! `model.rs` do not reach.
+
+extern crate adamantium;
+
+use std::sync::Arc;
+
+use adamantium::{Map, Set};
+use adamantium::map::{Bin, Included, Excluded, InvariantError};
+
+#[test]
+#[should_fail]
+fn from_sorted_iter_rejects_unsorted_input() {
+    let _: Map<uint, uint> = Map::from_sorted_iter(vec![(1u, 1u), (3, 3), (2, 2)].into_iter());
+}
+
+#[test]
+fn from_sorted_iter_keeps_the_last_value_of_duplicates() {
+    let map = Map::from_sorted_iter(vec![(1u, 1u), (2, 2), (2, 3), (2, 4), (5, 5), (5, 6)].into_iter());
+
+    assert!(map.validate().is_ok());
+    assert_eq!(map.len(), 3);
+    assert_eq!(map.iter().map(|(k, v)| (*k, *v)).collect::<Vec<(uint, uint)>>(),
+               vec![(1, 1), (2, 4), (5, 6)]);
+}
+
+#[test]
+fn string_keys_can_be_queried_with_str() {
+    let words = ["apple", "banana", "cherry", "damson"];
+    let map: Map<String, uint> = words.iter().enumerate().map(|(i, word)| (word.to_string(), i)).collect();
+    let set: Set<String> = words.iter().map(|word| word.to_string()).collect();
+
+    assert_eq!(map.get("banana"), Some(&1));
+    assert_eq!(map.get("blueberry"), None);
+    assert!(map.contains("cherry"));
+    assert!(!map.delete("cherry").contains("cherry"));
+    assert_eq!(map.delete("cherry").len(), 3);
+
+    let range: Vec<uint> = map.range(Included("b"), Excluded("d")).map(|(_, v)| *v).collect();
+    assert_eq!(range, vec![1, 2]);
+    let range: Vec<uint> = map.range_from("banana").map(|(_, v)| *v).collect();
+    assert_eq!(range, vec![1, 2, 3]);
+
+    assert!(set.contains("apple"));
+    assert!(!set.delete("apple").contains("apple"));
+
+    let range: Vec<&str> = set.range(Excluded("apple"), Included("cherry"))
+        .map(|word| word.as_slice())
+        .collect();
+    assert_eq!(range, vec!["banana", "cherry"]);
+    let range: Vec<&str> = set.range_to("cherry").map(|word| word.as_slice()).collect();
+    assert_eq!(range, vec!["apple", "banana"]);
+}
+
+// A map with a single pair, as a shared branch.
+fn leaf(key: uint) -> Arc<Map<uint, uint>> {
+    Arc::new(Map::singleton(key, key))
+}
+
+#[test]
+fn validate_reports_unordered_keys() {
+    let map = Map::bin(Arc::new(2), Arc::new(2), leaf(3), leaf(1));
+    assert_eq!(map.validate(), Err(InvariantError::Unordered { index: 0 }));
+}
+
+#[test]
+fn validate_reports_wrong_sizes() {
+    // `Map::bin` always caches the right size, so the node is built by hand.
+    let map = Bin { size: 5, key: Arc::new(2u), value: Arc::new(2u), left: leaf(1), right: leaf(3) };
+    assert_eq!(map.validate(), Err(InvariantError::WrongSize { index: 1, cached: 5, actual: 3 }));
+}
+
+#[test]
+fn validate_reports_unbalanced_branches() {
+    let chain = Map::bin(Arc::new(1), Arc::new(1), Arc::new(Map::new()),
+                         Arc::new(Map::bin(Arc::new(2), Arc::new(2), Arc::new(Map::new()), leaf(3))));
+    assert_eq!(chain.validate(), Err(InvariantError::Unbalanced { index: 0, left: 0, right: 2 }));
+}
+
+// The `validate` feature checks every map as it is built, so it is covered
+// by running the tests again with `cargo test --features validate`.
+#[test]
+#[should_fail]
+#[cfg(feature = "validate")]
+fn validate_feature_checks_maps_as_they_are_built() {
+    let _: Map<uint, uint> = Map::from_distinct_ascending(vec![(2u, 2u), (1, 1)].into_iter());
+}
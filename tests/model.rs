@@ -0,0 +1,2106 @@
+//! Randomized differential tests, which drive the persistent structures
+//! through long sequences of operations alongside a model from std and
+//! check that the two agree after every step.
+//!
+//! Failing sequences are shrunk before being reported, so the panic message
+//! contains a short list of operations which reproduces the bug.
+
+extern crate adamantium;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Show;
+use std::hash::{Hash, Hasher};
+use std::hash::sip::{mod, SipState};
+use std::rand::{Rng, SeedableRng, XorShiftRng};
+use std::sync::Arc;
+
+use adamantium::{HashMap, Heap, IntMap, IntSet, List, Map, PriorityQueue, Set, Stack, Trie};
+use adamantium::heap::{Compare, Descending};
+use adamantium::list::SharedList;
+use adamantium::map::{Bin, Tip, Bound, Included, Excluded, Unbounded, Occupied, Vacant};
+
+use self::MapOp::{Insert, Delete, Alter, Update, Adjust, Upsert, Batch, DeleteMin, DeleteMax};
+use self::MapOp::{Extract, PopMin, PopMax, UpdateLookup, OrInsertWith, ReplaceEntry, RemoveEntry};
+use self::ListOp::{Push, PushFront, Pop};
+use self::SetOp::{Add, Remove};
+use self::TrieOp::{Put, Take};
+use self::HeapOp::{Enqueue, Dequeue, Meld};
+
+// How many random sequences to run for each structure.
+static RUNS: uint = 200;
+
+// How many operations are in each sequence.
+static STEPS: uint = 300;
+
+// Keys are drawn from a small range so that operations often hit keys which
+// are already present.
+static KEYS: uint = 64;
+
+#[deriving(Clone, Show)]
+enum MapOp {
+    Insert(uint, uint),
+    Delete(uint),
+    Alter(uint, Option<uint>),
+    Update(uint, Option<uint>),
+    Adjust(uint, uint),
+
+    // Insert a value, or if the key is present combine the two values.
+    Upsert(uint, uint),
+
+    // Insertions, or deletions where there is no value, applied in order.
+    // Maps apply these through a transient.
+    Batch(Vec<(uint, Option<uint>)>),
+
+    DeleteMin,
+    DeleteMax,
+
+    // Deletions which also return what they removed.
+    Extract(uint),
+    PopMin,
+    PopMax,
+    UpdateLookup(uint, Option<uint>),
+
+    // Operations on an entry, which are no-ops for absent keys except for
+    // OrInsertWith.
+    OrInsertWith(uint, uint),
+    ReplaceEntry(uint, uint),
+    RemoveEntry(uint)
+}
+
+fn random_map_op<R: Rng>(rng: &mut R) -> MapOp {
+    let key = rng.gen_range(0, KEYS);
+
+    // Insertions are weighted up so that maps grow over a sequence.
+    match rng.gen_range(0u, 19) {
+        0 | 1 | 2 | 3 | 4 => Insert(key, rng.gen()),
+        3 => Delete(key),
+        4 => Alter(key, if rng.gen() { Some(rng.gen()) } else { None }),
+        5 => Update(key, if rng.gen() { Some(rng.gen()) } else { None }),
+        6 => Adjust(key, rng.gen()),
+        7 => Upsert(key, rng.gen()),
+        8 => {
+            let len = rng.gen_range(0u, 8);
+            Batch(Vec::from_fn(len, |_| {
+                let key = rng.gen_range(0, KEYS);
+                (key, if rng.gen_range(0u, 3) == 0 { None } else { Some(rng.gen()) })
+            }))
+        },
+        9 => DeleteMin,
+        10 => DeleteMax,
+        11 => Extract(key),
+        12 => PopMin,
+        13 => PopMax,
+        14 => UpdateLookup(key, if rng.gen() { Some(rng.gen()) } else { None }),
+        15 => OrInsertWith(key, rng.gen()),
+        16 => ReplaceEntry(key, rng.gen()),
+        _ => RemoveEntry(key)
+    }
+}
+
+// Apply a batch of insertions and deletions to a model.
+fn apply_batch<K: Ord + Clone>(model: &mut BTreeMap<K, uint>, batch: &[(K, Option<uint>)]) {
+    for &(ref key, value) in batch.iter() {
+        match value {
+            Some(value) => { model.insert(key.clone(), value); },
+            None => { model.remove(key); }
+        }
+    }
+}
+
+// Apply an upsert to a model.
+fn apply_upsert<K: Ord>(model: &mut BTreeMap<K, uint>, key: K, value: uint) {
+    let combined = match model.get(&key) {
+        Some(old) => *old ^ value,
+        None => value
+    };
+    model.insert(key, combined);
+}
+
+// Where the key stored in a map for `key` lives, if it is present.
+fn stored_key(map: &Map<uint, uint>, key: uint) -> Option<*const uint> {
+    match map.entry(Arc::new(key)) {
+        Occupied(entry) => Some(entry.key() as *const uint),
+        Vacant(_) => None
+    }
+}
+
+// Apply an operation to the model, and to the map to get its next version.
+// Upserts go through the entry API, half of them by way of
+// `insert_or_modify_with`, which must keep the key already in the map. The
+// pairs and values returned by deletions and entries are checked against the
+// model.
+fn apply_map(map: &Map<uint, uint>, model: &mut BTreeMap<uint, uint>, op: &MapOp)
+        -> Result<Map<uint, uint>, String> {
+    match *op {
+        Insert(key, value) => {
+            model.insert(key, value);
+            Ok(map.insert(Arc::new(key), Arc::new(value)))
+        },
+        Delete(key) => {
+            model.remove(&key);
+            Ok(map.delete(&key))
+        },
+        Alter(key, value) => {
+            match value {
+                Some(value) => { model.insert(key, value); },
+                None => { model.remove(&key); }
+            }
+            Ok(map.alter(Arc::new(key), |_, _| value))
+        },
+        Update(key, value) => {
+            if model.contains_key(&key) {
+                match value {
+                    Some(value) => { model.insert(key, value); },
+                    None => { model.remove(&key); }
+                }
+            }
+            Ok(map.update(&key, |_| value))
+        },
+        Adjust(key, mask) => {
+            match model.get_mut(&key) {
+                Some(value) => *value ^= mask,
+                None => {}
+            }
+            Ok(map.adjust(&key, |value| *value ^ mask))
+        },
+        Upsert(key, value) => {
+            apply_upsert(model, key, value);
+
+            if value % 2 == 0 {
+                Ok(map.entry(Arc::new(key)).and_modify(|old| *old ^ value).or_insert(Arc::new(value)))
+            } else {
+                let stored = stored_key(map, key);
+                let next = map.insert_or_modify_with(Arc::new(key), Arc::new(value), |old| *old ^ value);
+
+                if stored.is_some() && stored_key(&next, key) != stored {
+                    return Err(format!("insert_or_modify_with({}) replaced the key", key))
+                }
+                Ok(next)
+            }
+        },
+        Batch(ref batch) => {
+            apply_batch(model, batch.as_slice());
+
+            let mut transient = map.transient();
+            for &(key, value) in batch.iter() {
+                match value {
+                    Some(value) => transient.insert(Arc::new(key), Arc::new(value)),
+                    None => transient.delete(&key)
+                }
+            }
+            Ok(transient.persistent())
+        },
+        DeleteMin => {
+            let min = model.keys().next().map(|key| *key);
+            for key in min.iter() { model.remove(key); }
+            Ok(map.delete_min().unwrap_or(Map::new()))
+        },
+        DeleteMax => {
+            let max = model.keys().rev().next().map(|key| *key);
+            for key in max.iter() { model.remove(key); }
+            Ok(map.delete_max().unwrap_or(Map::new()))
+        },
+        Extract(key) => {
+            let expected = model.remove(&key).map(|value| (key, value));
+            let (removed, rest) = match map.remove(&key) {
+                Some(((k, v), rest)) => (Some((*k, *v)), rest),
+                None => (None, map.clone())
+            };
+
+            if removed != expected {
+                return Err(format!("remove({}) gave {}, expected {}", key, removed, expected))
+            }
+            Ok(rest)
+        },
+        PopMin | PopMax => {
+            let key = match *op {
+                PopMin => model.keys().next().map(|key| *key),
+                _ => model.keys().rev().next().map(|key| *key)
+            };
+            let expected = key.map(|key| (key, model.remove(&key).unwrap()));
+
+            let popped = match *op {
+                PopMin => map.pop_min(),
+                _ => map.pop_max()
+            };
+            let (removed, rest) = match popped {
+                Some(((k, v), rest)) => (Some((*k, *v)), rest),
+                None => (None, map.clone())
+            };
+
+            if removed != expected {
+                return Err(format!("popped {}, expected {}", removed, expected))
+            }
+            Ok(rest)
+        },
+        UpdateLookup(key, value) => {
+            let expected = model.get(&key).map(|old| *old);
+            if expected.is_some() {
+                match value {
+                    Some(value) => { model.insert(key, value); },
+                    None => { model.remove(&key); }
+                }
+            }
+
+            let (old, updated) = map.update_lookup(&key, |_| value);
+            let old = old.map(|old| *old);
+            if old != expected {
+                return Err(format!("update_lookup({}) gave {}, expected {}", key, old, expected))
+            }
+            Ok(updated)
+        },
+        OrInsertWith(key, value) => {
+            let present = model.contains_key(&key);
+            if !present { model.insert(key, value); }
+
+            let mut calls = 0u;
+            let next = map.entry(Arc::new(key)).or_insert_with(|| {
+                calls += 1;
+                value
+            });
+
+            let expected_calls = if present { 0 } else { 1 };
+            if calls != expected_calls {
+                return Err(format!("or_insert_with({}) called its closure {} times, expected {}",
+                                   key, calls, expected_calls))
+            }
+            Ok(next)
+        },
+        ReplaceEntry(key, _) | RemoveEntry(key) => {
+            let expected = model.get(&key).map(|old| *old);
+
+            match map.entry(Arc::new(key)) {
+                Occupied(entry) => {
+                    if *entry.key() != key || Some(*entry.get()) != expected {
+                        return Err(format!("entry({}) is {}, expected {}",
+                                           key, (*entry.key(), *entry.get()), expected))
+                    }
+
+                    match *op {
+                        ReplaceEntry(_, value) => {
+                            model.insert(key, value);
+                            Ok(entry.replace(Arc::new(value)))
+                        },
+                        _ => {
+                            model.remove(&key);
+                            Ok(entry.remove())
+                        }
+                    }
+                },
+                entry => {
+                    if expected.is_some() {
+                        return Err(format!("entry({}) is vacant, expected {}", key, expected))
+                    }
+                    Ok(entry.into_map())
+                }
+            }
+        }
+    }
+}
+
+fn check_map(map: &Map<uint, uint>, model: &BTreeMap<uint, uint>) -> Result<(), String> {
+    match map.validate() {
+        Ok(()) => {},
+        Err(err) => return Err(format!("invalid map: {}", err))
+    }
+
+    if map.len() != model.len() {
+        return Err(format!("len is {}, expected {}", map.len(), model.len()))
+    }
+
+    let actual: Vec<(uint, uint)> = map.iter().map(|(k, v)| (*k, *v)).collect();
+    let expected: Vec<(uint, uint)> = model.iter().map(|(k, v)| (*k, *v)).collect();
+    if actual != expected {
+        return Err(format!("contents are {}, expected {}", actual, expected))
+    }
+
+    for key in range(0, KEYS) {
+        if map.get(&key) != model.get(&key) {
+            return Err(format!("get({}) is {}, expected {}", key, map.get(&key), model.get(&key)))
+        }
+    }
+
+    let min = map.min().map(|(k, v)| (*k, *v));
+    if min != expected.iter().next().map(|&pair| pair) {
+        return Err(format!("min is {}, expected {}", min, expected.iter().next()))
+    }
+
+    let max = map.max().map(|(k, v)| (*k, *v));
+    if max != expected.last().map(|&pair| pair) {
+        return Err(format!("max is {}, expected {}", max, expected.last()))
+    }
+
+    Ok(())
+}
+
+// The kinds of bound tried at either end of a range query, by index.
+static BOUND_KINDS: [&'static str, ..3] = ["Included", "Excluded", "Unbounded"];
+
+fn bound<'a>(kind: uint, key: &'a uint) -> Bound<&'a uint> {
+    match kind {
+        0 => Included(key),
+        1 => Excluded(key),
+        _ => Unbounded
+    }
+}
+
+// Does `key` lie between the bounds of a range?
+fn in_range(key: uint, min: &Bound<&uint>, max: &Bound<&uint>) -> bool {
+    let above = match *min {
+        Included(&min) => key >= min,
+        Excluded(&min) => key > min,
+        Unbounded => true
+    };
+
+    let below = match *max {
+        Included(&max) => key <= max,
+        Excluded(&max) => key < max,
+        Unbounded => true
+    };
+
+    above && below
+}
+
+// A second key to bound ranges with, which is as often below `probe` as
+// above it, so that ranges with their minimum above their maximum are tried
+// too.
+fn other_probe(probe: uint) -> uint { (probe * 7 + 13) % KEYS }
+
+// Check the ordered queries of a map against the model, using `probe` as one
+// end of the ranges tried and to pick the positions tried.
+fn check_map_queries(map: &Map<uint, uint>, model: &BTreeMap<uint, uint>, probe: uint) -> Result<(), String> {
+    let pairs: Vec<(uint, uint)> = model.iter().map(|(k, v)| (*k, *v)).collect();
+
+    let other = other_probe(probe);
+    for min in range(0u, 3) {
+        for max in range(0u, 3) {
+            let expected: Vec<(uint, uint)> = pairs.iter().map(|&pair| pair)
+                .filter(|&(key, _)| in_range(key, &bound(min, &probe), &bound(max, &other)))
+                .collect();
+            let forward: Vec<(uint, uint)> = map.range(bound(min, &probe), bound(max, &other))
+                .map(|(k, v)| (*k, *v))
+                .collect();
+            let mut backward: Vec<(uint, uint)> = map.range(bound(min, &probe), bound(max, &other)).rev()
+                .map(|(k, v)| (*k, *v))
+                .collect();
+            backward.reverse();
+            let len = map.range(bound(min, &probe), bound(max, &other)).len();
+
+            if forward != expected || backward != expected || len != expected.len() {
+                return Err(format!("range({} {}, {} {}) is {} ({} backwards, len {}), expected {}",
+                                   BOUND_KINDS[min], probe, BOUND_KINDS[max], other,
+                                   forward, backward, len, expected))
+            }
+        }
+    }
+
+    let from: Vec<(uint, uint)> = map.range_from(&probe).map(|(k, v)| (*k, *v)).collect();
+    let expected: Vec<(uint, uint)> = pairs.iter().map(|&pair| pair).filter(|&(key, _)| key >= probe).collect();
+    if from != expected {
+        return Err(format!("range_from({}) is {}, expected {}", probe, from, expected))
+    }
+
+    let to: Vec<(uint, uint)> = map.range_to(&probe).map(|(k, v)| (*k, *v)).collect();
+    let expected: Vec<(uint, uint)> = pairs.iter().map(|&pair| pair).filter(|&(key, _)| key < probe).collect();
+    if to != expected {
+        return Err(format!("range_to({}) is {}, expected {}", probe, to, expected))
+    }
+
+    let index = pairs.iter().position(|&(key, _)| key == probe);
+    if map.index_of(&probe) != index {
+        return Err(format!("index_of({}) is {}, expected {}", probe, map.index_of(&probe), index))
+    }
+
+    for i in range(0, pairs.len() + 1) {
+        let actual = map.elem_at(i).map(|(k, v)| (*k, *v));
+        let expected = pairs.get(i).map(|&pair| pair);
+        if actual != expected {
+            return Err(format!("elem_at({}) is {}, expected {}", i, actual, expected))
+        }
+    }
+
+    let n = probe % (pairs.len() + 1);
+    let front: BTreeMap<uint, uint> = pairs.slice_to(n).iter().map(|&pair| pair).collect();
+    let back: BTreeMap<uint, uint> = pairs.slice_from(n).iter().map(|&pair| pair).collect();
+    try!(check_map(&map.take(n), &front).map_err(|err| format!("take({}): {}", n, err)));
+    try!(check_map(&map.drop(n), &back).map_err(|err| format!("drop({}): {}", n, err)));
+
+    let (left, right) = map.split_at(n);
+    try!(check_map(&left, &front).map_err(|err| format!("split_at({}) left: {}", n, err)));
+    try!(check_map(&right, &back).map_err(|err| format!("split_at({}) right: {}", n, err)));
+
+    match (map.delete_at(n), pairs.get(n)) {
+        (Some(deleted), Some(&(key, _))) => {
+            let mut expected = model.clone();
+            expected.remove(&key);
+            try!(check_map(&deleted, &expected).map_err(|err| format!("delete_at({}): {}", n, err)));
+        },
+        (None, None) => {},
+        (deleted, _) => {
+            return Err(format!("delete_at({}) is {}, expected {}", n, deleted.is_some(), n < pairs.len()))
+        }
+    }
+
+    Ok(())
+}
+
+// Check the set operations of two versions of a map against their models.
+// Where both maps have a key, the value from `a` is kept, or both values are
+// combined.
+fn check_map_ops(a: &Map<uint, uint>, b: &Map<uint, uint>, x: &BTreeMap<uint, uint>, y: &BTreeMap<uint, uint>)
+        -> Result<(), String> {
+    let mut union = y.clone();
+    union.extend(x.iter().map(|(k, v)| (*k, *v)));
+    try!(check_map(&a.union(b), &union).map_err(|err| format!("union: {}", err)));
+
+    let mut union_with = y.clone();
+    for (key, value) in x.iter() {
+        let combined = match y.get(key) {
+            Some(other) => *value ^ *other,
+            None => *value
+        };
+        union_with.insert(*key, combined);
+    }
+    try!(check_map(&a.union_with(b, |_, v, w| *v ^ *w), &union_with)
+         .map_err(|err| format!("union_with: {}", err)));
+
+    let intersection: BTreeMap<uint, uint> = x.iter()
+        .filter(|&(k, _)| y.contains_key(k))
+        .map(|(k, v)| (*k, *v))
+        .collect();
+    try!(check_map(&a.intersection(b), &intersection).map_err(|err| format!("intersection: {}", err)));
+
+    let intersection_with: BTreeMap<uint, uint> = x.iter()
+        .filter_map(|(k, v)| y.get(k).map(|w| (*k, *v ^ *w)))
+        .collect();
+    try!(check_map(&a.intersection_with(b, |_, v, w| *v ^ *w), &intersection_with)
+         .map_err(|err| format!("intersection_with: {}", err)));
+
+    let difference: BTreeMap<uint, uint> = x.iter()
+        .filter(|&(k, _)| !y.contains_key(k))
+        .map(|(k, v)| (*k, *v))
+        .collect();
+    try!(check_map(&a.difference(b), &difference).map_err(|err| format!("difference: {}", err)));
+
+    let symmetric: BTreeMap<uint, uint> = x.iter()
+        .filter(|&(k, _)| !y.contains_key(k))
+        .chain(y.iter().filter(|&(k, _)| !x.contains_key(k)))
+        .map(|(k, v)| (*k, *v))
+        .collect();
+    try!(check_map(&a.symmetric_difference(b), &symmetric)
+         .map_err(|err| format!("symmetric_difference: {}", err)));
+
+    let is_subset = x.keys().all(|k| y.contains_key(k));
+    if a.is_subset(b) != is_subset {
+        return Err(format!("is_subset is {}, expected {}", a.is_subset(b), is_subset))
+    }
+
+    let is_disjoint = x.keys().all(|k| !y.contains_key(k));
+    if a.is_disjoint(b) != is_disjoint {
+        return Err(format!("is_disjoint is {}, expected {}", a.is_disjoint(b), is_disjoint))
+    }
+
+    Ok(())
+}
+
+// Check collecting pairs into a map where a sorted prefix is followed by an
+// unsorted tail, and extending a map with pairs for keys which it already
+// has. The prefix is `probe` pairs long, if the map is that big.
+fn check_map_collect(map: &Map<uint, uint>, model: &BTreeMap<uint, uint>, probe: uint) -> Result<(), String> {
+    let pairs: Vec<(uint, uint)> = model.iter().map(|(k, v)| (*k, *v)).collect();
+    let n = probe % (pairs.len() + 1);
+
+    let mut unsorted: Vec<(uint, uint)> = pairs.slice_to(n).to_vec();
+    unsorted.extend(pairs.slice_from(n).iter().rev().map(|&pair| pair));
+    let collected: Map<uint, uint> = unsorted.into_iter().collect();
+    try!(check_map(&collected, model).map_err(|err| format!("collect with prefix {}: {}", n, err)));
+
+    // Every other key gets a new value, in descending order, and the probe is
+    // given two values of which the second must win.
+    let mut changes: Vec<(uint, uint)> = pairs.iter().rev()
+        .filter(|&&(k, _)| k % 2 == probe % 2)
+        .map(|&(k, v)| (k, v ^ probe))
+        .collect();
+    changes.push((probe, probe));
+    changes.push((probe, !probe));
+
+    let mut expected = model.clone();
+    expected.extend(changes.iter().map(|&pair| pair));
+    let mut extended = map.clone();
+    extended.extend(changes.into_iter());
+    try!(check_map(&extended, &expected).map_err(|err| format!("extend: {}", err)));
+
+    Ok(())
+}
+
+// Check splitting two versions of a map at `probe` against their models, and
+// that linking and merging the lower half of one with the upper half of the
+// other gives a valid map with the pairs from both sides.
+fn check_map_splits(a: &Map<uint, uint>, b: &Map<uint, uint>, x: &BTreeMap<uint, uint>, y: &BTreeMap<uint, uint>,
+                    probe: uint) -> Result<(), String> {
+    let below: BTreeMap<uint, uint> = x.iter().filter(|&(k, _)| *k < probe).map(|(k, v)| (*k, *v)).collect();
+    let above: BTreeMap<uint, uint> = x.iter().filter(|&(k, _)| *k > probe).map(|(k, v)| (*k, *v)).collect();
+    let found = x.get(&probe).map(|v| *v);
+
+    let (left, value, right) = a.split(&probe);
+    try!(check_map(&left, &below).map_err(|err| format!("split({}) left: {}", probe, err)));
+    try!(check_map(&right, &above).map_err(|err| format!("split({}) right: {}", probe, err)));
+    if value.as_ref().map(|v| **v) != found {
+        return Err(format!("split({}) found {}, expected {}", probe, value.map(|v| *v), found))
+    }
+
+    let (left, pair, right) = a.split_lookup(&probe);
+    try!(check_map(&left, &below).map_err(|err| format!("split_lookup({}) left: {}", probe, err)));
+    try!(check_map(&right, &above).map_err(|err| format!("split_lookup({}) right: {}", probe, err)));
+    let pair = pair.map(|(k, v)| (*k, *v));
+    if pair != found.map(|v| (probe, v)) {
+        return Err(format!("split_lookup({}) found {}, expected {}", probe, pair, found.map(|v| (probe, v))))
+    }
+
+    let (_, _, other_right) = b.split(&probe);
+    let mut merged = below.clone();
+    merged.extend(y.iter().filter(|&(k, _)| *k > probe).map(|(k, v)| (*k, *v)));
+    let merge = Map::merge(Arc::new(left.clone()), Arc::new(other_right.clone()));
+    try!(check_map(&merge, &merged).map_err(|err| format!("merge at {}: {}", probe, err)));
+
+    let mut linked = merged.clone();
+    linked.insert(probe, probe);
+    let link = Map::link(Arc::new(probe), Arc::new(probe), Arc::new(left), Arc::new(other_right));
+    try!(check_map(&link, &linked).map_err(|err| format!("link at {}: {}", probe, err)));
+
+    Ok(())
+}
+
+// Check the comparisons, hashes and formatting of two maps against those of
+// their models.
+fn check_map_compare(a: &Map<uint, uint>, b: &Map<uint, uint>, x: &BTreeMap<uint, uint>, y: &BTreeMap<uint, uint>)
+        -> Result<(), String> {
+    if (a == b) != (x == y) {
+        return Err(format!("== is {}, expected {}", a == b, x == y))
+    }
+
+    if a.cmp(b) != x.cmp(y) {
+        return Err(format!("cmp is {}, expected {}", a.cmp(b), x.cmp(y)))
+    }
+
+    if a.partial_cmp(b) != x.partial_cmp(y) {
+        return Err(format!("partial_cmp is {}, expected {}", a.partial_cmp(b), x.partial_cmp(y)))
+    }
+
+    let same_hash = sip::hash(a) == sip::hash(b);
+    if same_hash != (sip::hash(x) == sip::hash(y)) {
+        return Err(format!("hashes are {}, expected {}",
+                           if same_hash { "equal" } else { "different" },
+                           if same_hash { "different" } else { "equal" }))
+    }
+
+    if format!("{}", a) != format!("{}", x) {
+        return Err(format!("formatted as {}, expected {}", a, x))
+    }
+
+    Ok(())
+}
+
+// Compare a map with a version from earlier in the sequence, which shares
+// some of its structure, with a copy of that version built from scratch, and
+// with a version of itself changed at `probe`, in every combination.
+fn check_map_comparisons(a: &Map<uint, uint>, b: &Map<uint, uint>, x: &BTreeMap<uint, uint>,
+                         y: &BTreeMap<uint, uint>, probe: uint) -> Result<(), String> {
+    let rebuilt: Map<uint, uint> = y.iter().map(|(k, v)| (*k, *v)).collect();
+    let changed = a.insert(Arc::new(probe), Arc::new(probe));
+    let mut changed_model = x.clone();
+    changed_model.insert(probe, probe);
+
+    let maps = [("new", a, x), ("old", b, y), ("rebuilt old", &rebuilt, y), ("changed", &changed, &changed_model)];
+    for &(name, map, model) in maps.iter() {
+        for &(other, other_map, other_model) in maps.iter() {
+            try!(check_map_compare(map, other_map, model, other_model)
+                 .map_err(|err| format!("{} against {}: {}", name, other, err)));
+        }
+    }
+
+    Ok(())
+}
+
+// Whether a pair is kept by the predicates used to check transformations.
+// Kind 0 keeps every pair, kind 1 drops every pair, and kind 2 depends on
+// the key and value, with `probe` moving which pairs it keeps.
+fn keeps_pair(kind: uint, key: uint, value: uint, probe: uint) -> bool {
+    match kind {
+        0 => true,
+        1 => false,
+        _ => (key ^ value ^ probe) % 3 != 0
+    }
+}
+
+// Whether two maps are rebuilt around the very same branches.
+fn shares_branches(a: &Map<uint, uint>, b: &Map<uint, uint>) -> bool {
+    match (a, b) {
+        (&Bin { left: ref l, right: ref r, .. }, &Bin { left: ref m, right: ref s, .. }) => {
+            &**l as *const Map<uint, uint> == &**m as *const Map<uint, uint>
+                && &**r as *const Map<uint, uint> == &**s as *const Map<uint, uint>
+        },
+        (&Tip, &Tip) => true,
+        _ => false
+    }
+}
+
+// Check the transformations of a map against the same transformations of the
+// model, for predicates which keep every pair, no pair, and a mix of pairs
+// depending on their keys.
+fn check_map_transforms(map: &Map<uint, uint>, model: &BTreeMap<uint, uint>, probe: uint) -> Result<(), String> {
+    let values: BTreeMap<uint, uint> = model.iter().map(|(k, v)| (*k, *v ^ probe)).collect();
+    try!(check_map(&map.map_values(|v| *v ^ probe), &values).map_err(|err| format!("map_values: {}", err)));
+
+    let mut seen = Vec::new();
+    let with_key: BTreeMap<uint, uint> = model.iter().map(|(k, v)| (*k, *k ^ *v)).collect();
+    let mapped = map.map_with_key(|k, v| {
+        seen.push(*k);
+        *k ^ *v
+    });
+    try!(check_map(&mapped, &with_key).map_err(|err| format!("map_with_key: {}", err)));
+
+    let keys: Vec<uint> = model.keys().map(|k| *k).collect();
+    if seen != keys {
+        return Err(format!("map_with_key visited {}, expected {}", seen, keys))
+    }
+
+    for kind in range(0u, 3) {
+        let kept: BTreeMap<uint, uint> = model.iter()
+            .filter(|&(k, v)| keeps_pair(kind, *k, *v, probe))
+            .map(|(k, v)| (*k, *v))
+            .collect();
+        let dropped: BTreeMap<uint, uint> = model.iter()
+            .filter(|&(k, v)| !keeps_pair(kind, *k, *v, probe))
+            .map(|(k, v)| (*k, *v))
+            .collect();
+
+        let filtered = map.filter(|k, v| keeps_pair(kind, *k, *v, probe));
+        try!(check_map(&filtered, &kept).map_err(|err| format!("filter {}: {}", kind, err)));
+
+        // Keeping every pair reuses the branches of the original map.
+        if kind == 0 && !shares_branches(&filtered, map) {
+            return Err(format!("filter keeping every pair copied the map"))
+        }
+
+        let filter_mapped: BTreeMap<uint, uint> = kept.iter().map(|(k, v)| (*k, *k ^ *v)).collect();
+        let actual = map.filter_map(|k, v| if keeps_pair(kind, *k, *v, probe) { Some(*k ^ *v) } else { None });
+        try!(check_map(&actual, &filter_mapped).map_err(|err| format!("filter_map {}: {}", kind, err)));
+
+        let (yes, no) = map.partition(|k, v| keeps_pair(kind, *k, *v, probe));
+        try!(check_map(&yes, &kept).map_err(|err| format!("partition {} kept: {}", kind, err)));
+        try!(check_map(&no, &dropped).map_err(|err| format!("partition {} dropped: {}", kind, err)));
+
+        let (ok, err) = map.map_either(|k, v| {
+            if keeps_pair(kind, *k, *v, probe) { Ok(*k ^ *v) } else { Err(*v ^ probe) }
+        });
+        let err_model: BTreeMap<uint, uint> = dropped.iter().map(|(k, v)| (*k, *v ^ probe)).collect();
+        try!(check_map(&ok, &filter_mapped).map_err(|err| format!("map_either {} ok: {}", kind, err)));
+        try!(check_map(&err, &err_model).map_err(|err| format!("map_either {} err: {}", kind, err)));
+    }
+
+    Ok(())
+}
+
+// Check the order in which the folds visit a map, in both directions, and
+// that the fallible folds stop calling their closure at the first error. The
+// error is returned at the pair in position `probe`, if there is one.
+fn check_map_folds(map: &Map<uint, uint>, model: &BTreeMap<uint, uint>, probe: uint) -> Result<(), String> {
+    let pairs: Vec<(uint, uint)> = model.iter().map(|(k, v)| (*k, *v)).collect();
+    let values: Vec<uint> = pairs.iter().map(|&(_, v)| v).collect();
+    let mut reversed = pairs.clone();
+    reversed.reverse();
+    let reversed_values: Vec<uint> = reversed.iter().map(|&(_, v)| v).collect();
+
+    let fold = map.fold(Vec::new(), |mut acc, v| { acc.push(*v); acc });
+    if fold != values {
+        return Err(format!("fold visited {}, expected {}", fold, values))
+    }
+
+    let fold_right = map.fold_right(Vec::new(), |v, mut acc| { acc.push(*v); acc });
+    if fold_right != reversed_values {
+        return Err(format!("fold_right visited {}, expected {}", fold_right, reversed_values))
+    }
+
+    let with_key = map.fold_with_key(Vec::new(), |mut acc, k, v| { acc.push((*k, *v)); acc });
+    if with_key != pairs {
+        return Err(format!("fold_with_key visited {}, expected {}", with_key, pairs))
+    }
+
+    let right_with_key = map.fold_right_with_key(Vec::new(), |k, v, mut acc| { acc.push((*k, *v)); acc });
+    if right_with_key != reversed {
+        return Err(format!("fold_right_with_key visited {}, expected {}", right_with_key, reversed))
+    }
+
+    let n = probe % (pairs.len() + 1);
+    let expected = match pairs.get(n) {
+        Some(&(key, _)) => Err(key),
+        None => Ok(pairs.clone())
+    };
+    let calls_expected = if n < pairs.len() { n + 1 } else { pairs.len() };
+
+    let mut calls = 0u;
+    let tried = map.try_fold(Vec::new(), |mut acc, k, v| {
+        calls += 1;
+        if calls == n + 1 { return Err(*k) }
+        acc.push((*k, *v));
+        Ok(acc)
+    });
+    if tried != expected || calls != calls_expected {
+        return Err(format!("try_fold gave {} after {} calls, expected {} after {}",
+                           tried, calls, expected, calls_expected))
+    }
+
+    let mut calls = 0u;
+    let traversed = map.traverse(|k, v| {
+        calls += 1;
+        if calls == n + 1 { Err(*k) } else { Ok(*k ^ *v) }
+    });
+    if calls != calls_expected {
+        return Err(format!("traverse made {} calls, expected {}", calls, calls_expected))
+    }
+    match (traversed, expected) {
+        (Ok(traversed), Ok(_)) => {
+            let mapped: BTreeMap<uint, uint> = pairs.iter().map(|&(k, v)| (k, k ^ v)).collect();
+            try!(check_map(&traversed, &mapped).map_err(|err| format!("traverse: {}", err)));
+        },
+        (Err(key), Err(expected)) if key == expected => {},
+        (traversed, expected) => {
+            return Err(format!("traverse gave {}, expected {}",
+                               traversed.map(|_| ()), expected.map(|_| ())))
+        }
+    }
+
+    Ok(())
+}
+
+// Walk a map recursively, collecting its pairs in preorder, in postorder,
+// and by depth for a breadth-first order.
+fn walk_map(map: &Map<uint, uint>, depth: uint, pre: &mut Vec<(uint, uint)>, post: &mut Vec<(uint, uint)>,
+            levels: &mut Vec<Vec<(uint, uint)>>) {
+    match *map {
+        Tip => {},
+        Bin { ref key, ref value, ref left, ref right, .. } => {
+            if levels.len() == depth { levels.push(Vec::new()) }
+            levels[depth].push((**key, **value));
+            pre.push((**key, **value));
+            walk_map(&**left, depth + 1, pre, post, levels);
+            walk_map(&**right, depth + 1, pre, post, levels);
+            post.push((**key, **value));
+        }
+    }
+}
+
+// Check that an iterator yields the expected pairs, and that its size hint
+// is exact before every step.
+fn check_traversal<'a, I: Iterator<(&'a uint, &'a uint)>>(name: &str, mut iter: I, expected: &[(uint, uint)])
+        -> Result<(), String> {
+    for (i, &pair) in expected.iter().enumerate() {
+        let remaining = expected.len() - i;
+        if iter.size_hint() != (remaining, Some(remaining)) {
+            return Err(format!("{} size_hint is {} at {}, expected {}", name, iter.size_hint(), i, remaining))
+        }
+
+        match iter.next() {
+            Some((k, v)) if (*k, *v) == pair => {},
+            other => {
+                return Err(format!("{} gave {} at {}, expected {}",
+                                   name, other.map(|(k, v)| (*k, *v)), i, pair))
+            }
+        }
+    }
+
+    if iter.size_hint() != (0, Some(0)) {
+        return Err(format!("{} size_hint is {} at the end", name, iter.size_hint()))
+    }
+
+    match iter.next() {
+        Some((k, v)) => Err(format!("{} gave {} past the end", name, (*k, *v))),
+        None => Ok(())
+    }
+}
+
+// Check the traversals of a map against a recursive walk of its nodes, and
+// that their `arcs` adaptors yield the very Arcs stored in the map.
+fn check_map_traversals(map: &Map<uint, uint>) -> Result<(), String> {
+    let (mut pre, mut post, mut levels) = (Vec::new(), Vec::new(), Vec::new());
+    walk_map(map, 0, &mut pre, &mut post, &mut levels);
+    let bfs: Vec<(uint, uint)> = levels.into_iter().flat_map(|level| level.into_iter()).collect();
+
+    try!(check_traversal("preorder_iter", map.preorder_iter(), pre.as_slice()));
+    try!(check_traversal("postorder_iter", map.postorder_iter(), post.as_slice()));
+    try!(check_traversal("bfs_iter", map.bfs_iter(), bfs.as_slice()));
+
+    let arcs = vec![
+        ("inorder_iter", map.inorder_iter().arcs().collect::<Vec<(Arc<uint>, Arc<uint>)>>()),
+        ("inorder_iter reversed", map.inorder_iter().arcs().rev().collect()),
+        ("preorder_iter", map.preorder_iter().arcs().collect()),
+        ("postorder_iter", map.postorder_iter().arcs().collect()),
+        ("bfs_iter", map.bfs_iter().arcs().collect())
+    ];
+
+    for &(name, ref pairs) in arcs.iter() {
+        if pairs.len() != map.len() {
+            return Err(format!("{} arcs gave {} pairs, expected {}", name, pairs.len(), map.len()))
+        }
+
+        for &(ref key, ref value) in pairs.iter() {
+            match map.get(&**key) {
+                Some(found) if found as *const uint == &**value as *const uint => {},
+                _ => return Err(format!("{} arcs gave a value for {} which is not in the map", name, **key))
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// The key an operation on a map is about, used to probe the map's queries.
+// Operations without a single key fall back to one picked by the step.
+fn map_probe(op: &MapOp, step: uint) -> uint {
+    match *op {
+        Insert(key, _) | Delete(key) | Alter(key, _) | Update(key, _) => key,
+        Adjust(key, _) | Upsert(key, _) | Extract(key) | UpdateLookup(key, _) => key,
+        OrInsertWith(key, _) | ReplaceEntry(key, _) | RemoveEntry(key) => key,
+        Batch(..) | DeleteMin | DeleteMax | PopMin | PopMax => step % KEYS
+    }
+}
+
+// Run a sequence of operations starting from an empty map, describing the
+// first step at which the map and the model disagree.
+//
+// Checking the old versions again at the end also catches a transient which
+// updates the nodes it shares with the map it came from instead of copying
+// them.
+fn run_map(ops: &[MapOp]) -> Result<(), String> {
+    let mut map = Map::new();
+    let mut model = BTreeMap::new();
+    let mut versions = vec![(map.clone(), model.clone())];
+
+    for (step, op) in ops.iter().enumerate() {
+        map = match apply_map(&map, &mut model, op) {
+            Ok(next) => next,
+            Err(err) => return Err(format!("step {} ({}): {}", step, op, err))
+        };
+        let probe = map_probe(op, step);
+
+        let checked = check_map(&map, &model)
+            .and_then(|()| check_map_queries(&map, &model, probe))
+            .and_then(|()| check_map_collect(&map, &model, probe))
+            .and_then(|()| check_map_transforms(&map, &model, probe))
+            .and_then(|()| check_map_folds(&map, &model, probe))
+            .and_then(|()| check_map_traversals(&map))
+            .and_then(|()| {
+                let (ref old, ref old_model) = versions[versions.len() / 2];
+                check_map_ops(&map, old, &model, old_model)
+                    .and_then(|()| check_map_splits(&map, old, &model, old_model, probe))
+                    .and_then(|()| check_map_comparisons(&map, old, &model, old_model, probe))
+            });
+
+        match checked {
+            Ok(()) => versions.push((map.clone(), model.clone())),
+            Err(err) => return Err(format!("step {} ({}): {}", step, op, err))
+        }
+    }
+
+    check_versions(versions.as_slice(), |map, model| check_map(map, model))
+}
+
+// A hasher which only keeps a few bits of the hash at either end, so that
+// hash maps with a handful of keys have both collisions and deep branches.
+#[deriving(Clone, Default)]
+struct CollidingHasher;
+
+impl Hasher<SipState> for CollidingHasher {
+    fn hash<Sized? T: Hash<SipState>>(&self, value: &T) -> u64 {
+        sip::hash(value) & 0xf000_0000_0000_000f
+    }
+}
+
+// Apply an operation to the model, and to the hash map to get its next
+// version. Hash maps are not ordered, so the model says which key to delete
+// for the operations on the smallest and largest keys. Deletions go through
+// `remove`, whose removed pair is checked against the model, and
+// UpdateLookup is applied as a plain update.
+fn apply_hash_map(map: &HashMap<uint, uint, CollidingHasher>, model: &mut BTreeMap<uint, uint>, op: &MapOp)
+        -> Result<HashMap<uint, uint, CollidingHasher>, String> {
+    let key = match *op {
+        Insert(key, value) => {
+            model.insert(key, value);
+            return Ok(map.insert(Arc::new(key), Arc::new(value)))
+        },
+        Alter(key, value) => {
+            match value {
+                Some(value) => { model.insert(key, value); },
+                None => { model.remove(&key); }
+            }
+            return Ok(map.alter(Arc::new(key), |_, _| value))
+        },
+        Update(key, value) | UpdateLookup(key, value) => {
+            if model.contains_key(&key) {
+                match value {
+                    Some(value) => { model.insert(key, value); },
+                    None => { model.remove(&key); }
+                }
+            }
+            return Ok(map.update(&key, |_| value))
+        },
+        Adjust(key, mask) => {
+            match model.get_mut(&key) {
+                Some(value) => *value ^= mask,
+                None => {}
+            }
+            return Ok(map.adjust(&key, |value| *value ^ mask))
+        },
+        Upsert(key, value) => {
+            apply_upsert(model, key, value);
+            return Ok(map.alter(Arc::new(key), |_, old| Some(old.map_or(value, |old| *old ^ value))))
+        },
+        OrInsertWith(key, value) => {
+            if !model.contains_key(&key) { model.insert(key, value); }
+            return Ok(map.alter(Arc::new(key), |_, old| Some(old.map_or(value, |old| *old))))
+        },
+        ReplaceEntry(key, value) => {
+            if model.contains_key(&key) { model.insert(key, value); }
+            return Ok(map.adjust(&key, |_| value))
+        },
+        Batch(ref batch) => {
+            apply_batch(model, batch.as_slice());
+            return Ok(batch.iter().fold(map.clone(), |map, &(key, value)| {
+                match value {
+                    Some(value) => map.insert(Arc::new(key), Arc::new(value)),
+                    None => map.delete(&key)
+                }
+            }))
+        },
+        Delete(key) | Extract(key) | RemoveEntry(key) => key,
+        DeleteMin | PopMin => match model.keys().next() {
+            Some(&key) => key,
+            None => return Ok(map.clone())
+        },
+        DeleteMax | PopMax => match model.keys().rev().next() {
+            Some(&key) => key,
+            None => return Ok(map.clone())
+        }
+    };
+
+    let expected = model.remove(&key).map(|value| (key, value));
+    match map.remove(&key) {
+        Some(((k, v), rest)) => {
+            if Some((*k, *v)) != expected {
+                return Err(format!("remove({}) gave {}, expected {}", key, (*k, *v), expected))
+            }
+            Ok(rest)
+        },
+        None => {
+            if expected.is_some() {
+                return Err(format!("remove({}) gave nothing, expected {}", key, expected))
+            }
+            Ok(map.clone())
+        }
+    }
+}
+
+fn check_hash_map(map: &HashMap<uint, uint, CollidingHasher>, model: &BTreeMap<uint, uint>)
+        -> Result<(), String> {
+    if map.len() != model.len() {
+        return Err(format!("len is {}, expected {}", map.len(), model.len()))
+    }
+
+    let mut actual: Vec<(uint, uint)> = map.iter().map(|(k, v)| (*k, *v)).collect();
+    actual.sort();
+    let expected: Vec<(uint, uint)> = model.iter().map(|(k, v)| (*k, *v)).collect();
+    if actual != expected {
+        return Err(format!("contents are {}, expected {}", actual, expected))
+    }
+
+    for key in range(0, KEYS) {
+        if map.get(&key) != model.get(&key) {
+            return Err(format!("get({}) is {}, expected {}", key, map.get(&key), model.get(&key)))
+        }
+    }
+
+    Ok(())
+}
+
+// Run a sequence of operations starting from an empty hash map, describing
+// the first step at which the hash map and the model disagree.
+fn run_hash_map(ops: &[MapOp]) -> Result<(), String> {
+    let mut map = HashMap::with_hasher(CollidingHasher);
+    let mut model = BTreeMap::new();
+    let mut versions = Vec::new();
+
+    for (step, op) in ops.iter().enumerate() {
+        let result = apply_hash_map(&map, &mut model, op)
+            .and_then(|next| check_hash_map(&next, &model).map(|()| next));
+
+        match result {
+            Ok(next) => {
+                map = next;
+                versions.push((map.clone(), model.clone()));
+            },
+            Err(err) => return Err(format!("step {} ({}): {}", step, op, err))
+        }
+    }
+
+    check_versions(versions.as_slice(), |map, model| check_hash_map(map, model))
+}
+
+// Spread the small keys used by the operations over the whole range of u64,
+// so that integer maps branch on high bits as well as low ones.
+fn spread(key: uint) -> u64 { (key as u64) * 0x9e37_79b9_7f4a_7c15 }
+
+// Apply an operation to the model, and to the integer map to get its next
+// version. Like hash maps, integer maps take the smallest and largest keys
+// from the model, and apply the operations which return what they removed
+// as plain deletions and updates.
+fn apply_int_map(map: &IntMap<uint>, model: &mut BTreeMap<u64, uint>, op: &MapOp) -> IntMap<uint> {
+    match *op {
+        Insert(key, value) => {
+            model.insert(spread(key), value);
+            map.insert(spread(key), Arc::new(value))
+        },
+        Delete(key) | Extract(key) | RemoveEntry(key) => {
+            model.remove(&spread(key));
+            map.delete(spread(key))
+        },
+        Alter(key, value) => {
+            match value {
+                Some(value) => { model.insert(spread(key), value); },
+                None => { model.remove(&spread(key)); }
+            }
+            map.alter(spread(key), |_| value)
+        },
+        Update(key, value) | UpdateLookup(key, value) => {
+            if model.contains_key(&spread(key)) {
+                match value {
+                    Some(value) => { model.insert(spread(key), value); },
+                    None => { model.remove(&spread(key)); }
+                }
+            }
+            map.update(spread(key), |_| value)
+        },
+        Adjust(key, mask) => {
+            match model.get_mut(&spread(key)) {
+                Some(value) => *value ^= mask,
+                None => {}
+            }
+            map.adjust(spread(key), |value| *value ^ mask)
+        },
+        Upsert(key, value) => {
+            apply_upsert(model, spread(key), value);
+            map.alter(spread(key), |old| Some(old.map_or(value, |old| *old ^ value)))
+        },
+        OrInsertWith(key, value) => {
+            if !model.contains_key(&spread(key)) { model.insert(spread(key), value); }
+            map.alter(spread(key), |old| Some(old.map_or(value, |old| *old)))
+        },
+        ReplaceEntry(key, value) => {
+            if model.contains_key(&spread(key)) { model.insert(spread(key), value); }
+            map.adjust(spread(key), |_| value)
+        },
+        Batch(ref batch) => {
+            let spread_batch: Vec<(u64, Option<uint>)> = batch.iter()
+                .map(|&(key, value)| (spread(key), value))
+                .collect();
+            apply_batch(model, spread_batch.as_slice());
+
+            spread_batch.iter().fold(map.clone(), |map, &(key, value)| {
+                match value {
+                    Some(value) => map.insert(key, Arc::new(value)),
+                    None => map.delete(key)
+                }
+            })
+        },
+        DeleteMin | DeleteMax | PopMin | PopMax => {
+            let key = match *op {
+                DeleteMin | PopMin => model.keys().next().map(|key| *key),
+                _ => model.keys().rev().next().map(|key| *key)
+            };
+
+            match key {
+                Some(key) => {
+                    model.remove(&key);
+                    map.delete(key)
+                },
+                None => map.clone()
+            }
+        }
+    }
+}
+
+fn check_int_map(map: &IntMap<uint>, model: &BTreeMap<u64, uint>) -> Result<(), String> {
+    if map.len() != model.len() {
+        return Err(format!("len is {}, expected {}", map.len(), model.len()))
+    }
+
+    let actual: Vec<(u64, uint)> = map.iter().map(|(k, v)| (k, *v)).collect();
+    let expected: Vec<(u64, uint)> = model.iter().map(|(k, v)| (*k, *v)).collect();
+    if actual != expected {
+        return Err(format!("contents are {}, expected {}", actual, expected))
+    }
+
+    for key in range(0, KEYS) {
+        if map.get(spread(key)) != model.get(&spread(key)) {
+            return Err(format!("get({}) is {}, expected {}",
+                               key, map.get(spread(key)), model.get(&spread(key))))
+        }
+    }
+
+    Ok(())
+}
+
+// Check the set operations of two versions of an integer map against their
+// models.
+fn check_int_map_ops(a: &IntMap<uint>, b: &IntMap<uint>, x: &BTreeMap<u64, uint>, y: &BTreeMap<u64, uint>)
+        -> Result<(), String> {
+    let mut union = y.clone();
+    union.extend(x.iter().map(|(k, v)| (*k, *v)));
+    try!(check_int_map(&a.union(b), &union).map_err(|err| format!("union: {}", err)));
+
+    let intersection: BTreeMap<u64, uint> = x.iter()
+        .filter(|&(k, _)| y.contains_key(k))
+        .map(|(k, v)| (*k, *v))
+        .collect();
+    try!(check_int_map(&a.intersection(b), &intersection).map_err(|err| format!("intersection: {}", err)));
+
+    let difference: BTreeMap<u64, uint> = x.iter()
+        .filter(|&(k, _)| !y.contains_key(k))
+        .map(|(k, v)| (*k, *v))
+        .collect();
+    try!(check_int_map(&a.difference(b), &difference).map_err(|err| format!("difference: {}", err)));
+
+    Ok(())
+}
+
+// Run a sequence of operations starting from an empty integer map,
+// describing the first step at which the map and the model disagree.
+fn run_int_map(ops: &[MapOp]) -> Result<(), String> {
+    let mut map = IntMap::new();
+    let mut model = BTreeMap::new();
+    let mut versions = vec![(map.clone(), model.clone())];
+
+    for (step, op) in ops.iter().enumerate() {
+        map = apply_int_map(&map, &mut model, op);
+
+        match check_int_map(&map, &model) {
+            Ok(()) => {},
+            Err(err) => return Err(format!("step {} ({}): {}", step, op, err))
+        }
+
+        let checked = {
+            let (ref old, ref old_model) = versions[versions.len() / 2];
+            check_int_map_ops(&map, old, &model, old_model)
+        };
+
+        match checked {
+            Ok(()) => {},
+            Err(err) => return Err(format!("step {} ({}): {}", step, op, err))
+        }
+
+        versions.push((map.clone(), model.clone()));
+    }
+
+    check_versions(versions.as_slice(), |map, model| check_int_map(map, model))
+}
+
+#[deriving(Clone, Show)]
+enum SetOp {
+    Add(uint),
+    Remove(uint)
+}
+
+fn random_set_op<R: Rng>(rng: &mut R) -> SetOp {
+    let key = rng.gen_range(0, KEYS);
+    if rng.gen_range(0u, 3) == 0 { Remove(key) } else { Add(key) }
+}
+
+fn apply_set(set: &Set<uint>, model: &mut BTreeSet<uint>, op: &SetOp) -> Set<uint> {
+    match *op {
+        Add(key) => {
+            model.insert(key);
+            set.insert(Arc::new(key))
+        },
+        Remove(key) => {
+            model.remove(&key);
+            set.delete(&key)
+        }
+    }
+}
+
+fn check_set(set: &Set<uint>, model: &BTreeSet<uint>) -> Result<(), String> {
+    match set.validate() {
+        Ok(()) => {},
+        Err(err) => return Err(format!("invalid set: {}", err))
+    }
+
+    let actual: Vec<uint> = set.iter().map(|key| *key).collect();
+    let expected: Vec<uint> = model.iter().map(|key| *key).collect();
+    if actual != expected {
+        return Err(format!("contents are {}, expected {}", actual, expected))
+    }
+
+    if set.len() != model.len() {
+        return Err(format!("len is {}, expected {}", set.len(), model.len()))
+    }
+
+    Ok(())
+}
+
+// Check the ordered queries of a set against the model, using `probe` as the
+// key to look up and as one end of the ranges and positions tried.
+fn check_set_queries(set: &Set<uint>, model: &BTreeSet<uint>, probe: uint) -> Result<(), String> {
+    let keys: Vec<uint> = model.iter().map(|key| *key).collect();
+
+    let lookups = [
+        ("lookup_lt", set.lookup_lt(&probe), keys.iter().rev().find(|&&key| key < probe)),
+        ("lookup_gt", set.lookup_gt(&probe), keys.iter().find(|&&key| key > probe)),
+        ("lookup_le", set.lookup_le(&probe), keys.iter().rev().find(|&&key| key <= probe)),
+        ("lookup_ge", set.lookup_ge(&probe), keys.iter().find(|&&key| key >= probe))
+    ];
+    for &(name, actual, expected) in lookups.iter() {
+        if actual != expected {
+            return Err(format!("{}({}) is {}, expected {}", name, probe, actual, expected))
+        }
+    }
+
+    let other = other_probe(probe);
+    for min in range(0u, 3) {
+        for max in range(0u, 3) {
+            let expected: Vec<uint> = keys.iter().map(|&key| key)
+                .filter(|&key| in_range(key, &bound(min, &probe), &bound(max, &other)))
+                .collect();
+            let forward: Vec<uint> = set.range(bound(min, &probe), bound(max, &other)).map(|key| *key).collect();
+            let mut backward: Vec<uint> = set.range(bound(min, &probe), bound(max, &other)).rev()
+                .map(|key| *key)
+                .collect();
+            backward.reverse();
+            let len = set.range(bound(min, &probe), bound(max, &other)).len();
+
+            if forward != expected || backward != expected || len != expected.len() {
+                return Err(format!("range({} {}, {} {}) is {} ({} backwards, len {}), expected {}",
+                                   BOUND_KINDS[min], probe, BOUND_KINDS[max], other,
+                                   forward, backward, len, expected))
+            }
+        }
+    }
+
+    let index = keys.iter().position(|&key| key == probe);
+    if set.index_of(&probe) != index {
+        return Err(format!("index_of({}) is {}, expected {}", probe, set.index_of(&probe), index))
+    }
+
+    for i in range(0, keys.len() + 1) {
+        if set.elem_at(i) != keys.get(i) {
+            return Err(format!("elem_at({}) is {}, expected {}", i, set.elem_at(i), keys.get(i)))
+        }
+    }
+
+    let n = probe % (keys.len() + 1);
+    let front: BTreeSet<uint> = keys.slice_to(n).iter().map(|key| *key).collect();
+    let back: BTreeSet<uint> = keys.slice_from(n).iter().map(|key| *key).collect();
+    try!(check_set(&set.take(n), &front).map_err(|err| format!("take({}): {}", n, err)));
+    try!(check_set(&set.drop(n), &back).map_err(|err| format!("drop({}): {}", n, err)));
+
+    let (left, right) = set.split_at(n);
+    try!(check_set(&left, &front).map_err(|err| format!("split_at({}) left: {}", n, err)));
+    try!(check_set(&right, &back).map_err(|err| format!("split_at({}) right: {}", n, err)));
+
+    match (set.delete_at(n), keys.get(n)) {
+        (Some(deleted), Some(key)) => {
+            let mut expected = model.clone();
+            expected.remove(key);
+            try!(check_set(&deleted, &expected).map_err(|err| format!("delete_at({}): {}", n, err)));
+        },
+        (None, None) => {},
+        (deleted, _) => {
+            return Err(format!("delete_at({}) is {}, expected {}", n, deleted.is_some(), n < keys.len()))
+        }
+    }
+
+    Ok(())
+}
+
+// Check the set operations of two versions of a set against their models.
+fn check_set_ops(a: &Set<uint>, b: &Set<uint>, x: &BTreeSet<uint>, y: &BTreeSet<uint>) -> Result<(), String> {
+    let union: BTreeSet<uint> = x.union(y).map(|key| *key).collect();
+    try!(check_set(&a.union(b), &union).map_err(|err| format!("union: {}", err)));
+
+    let intersection: BTreeSet<uint> = x.intersection(y).map(|key| *key).collect();
+    try!(check_set(&a.intersection(b), &intersection).map_err(|err| format!("intersection: {}", err)));
+
+    let difference: BTreeSet<uint> = x.difference(y).map(|key| *key).collect();
+    try!(check_set(&a.difference(b), &difference).map_err(|err| format!("difference: {}", err)));
+
+    let symmetric: BTreeSet<uint> = x.symmetric_difference(y).map(|key| *key).collect();
+    try!(check_set(&a.symmetric_difference(b), &symmetric)
+         .map_err(|err| format!("symmetric_difference: {}", err)));
+
+    if a.is_subset(b) != x.is_subset(y) {
+        return Err(format!("is_subset is {}, expected {}", a.is_subset(b), x.is_subset(y)))
+    }
+
+    if a.is_disjoint(b) != x.is_disjoint(y) {
+        return Err(format!("is_disjoint is {}, expected {}", a.is_disjoint(b), x.is_disjoint(y)))
+    }
+
+    Ok(())
+}
+
+// Run a sequence of operations starting from an empty set, describing the
+// first step at which the set and the model disagree.
+fn run_set(ops: &[SetOp]) -> Result<(), String> {
+    let mut set = Set::new();
+    let mut model = BTreeSet::new();
+    let mut versions = vec![(set.clone(), model.clone())];
+
+    for (step, op) in ops.iter().enumerate() {
+        set = apply_set(&set, &mut model, op);
+
+        let probe = match *op { Add(key) | Remove(key) => key };
+        match check_set(&set, &model).and_then(|()| check_set_queries(&set, &model, probe)) {
+            Ok(()) => {},
+            Err(err) => return Err(format!("step {} ({}): {}", step, op, err))
+        }
+
+        let checked = {
+            let (ref old, ref old_model) = versions[versions.len() / 2];
+            check_set_ops(&set, old, &model, old_model)
+        };
+
+        match checked {
+            Ok(()) => {},
+            Err(err) => return Err(format!("step {} ({}): {}", step, op, err))
+        }
+
+        versions.push((set.clone(), model.clone()));
+    }
+
+    check_versions(versions.as_slice(), |set, model| check_set(set, model))
+}
+
+// Apply an operation to the model, and to the integer set to get its next
+// version. Keys are spread as for integer maps.
+fn apply_int_set(set: &IntSet, model: &mut BTreeSet<u64>, op: &SetOp) -> IntSet {
+    match *op {
+        Add(key) => {
+            model.insert(spread(key));
+            set.insert(spread(key))
+        },
+        Remove(key) => {
+            model.remove(&spread(key));
+            set.delete(spread(key))
+        }
+    }
+}
+
+fn check_int_set(set: &IntSet, model: &BTreeSet<u64>) -> Result<(), String> {
+    if set.len() != model.len() {
+        return Err(format!("len is {}, expected {}", set.len(), model.len()))
+    }
+
+    let actual: Vec<u64> = set.iter().collect();
+    let expected: Vec<u64> = model.iter().map(|key| *key).collect();
+    if actual != expected {
+        return Err(format!("contents are {}, expected {}", actual, expected))
+    }
+
+    for key in range(0, KEYS) {
+        if set.contains(spread(key)) != model.contains(&spread(key)) {
+            return Err(format!("contains({}) is {}, expected {}",
+                               key, set.contains(spread(key)), model.contains(&spread(key))))
+        }
+    }
+
+    Ok(())
+}
+
+// Check the set operations of two versions of an integer set against their
+// models.
+fn check_int_set_ops(a: &IntSet, b: &IntSet, x: &BTreeSet<u64>, y: &BTreeSet<u64>) -> Result<(), String> {
+    let union: BTreeSet<u64> = x.union(y).map(|key| *key).collect();
+    try!(check_int_set(&a.union(b), &union).map_err(|err| format!("union: {}", err)));
+
+    let intersection: BTreeSet<u64> = x.intersection(y).map(|key| *key).collect();
+    try!(check_int_set(&a.intersection(b), &intersection).map_err(|err| format!("intersection: {}", err)));
+
+    let difference: BTreeSet<u64> = x.difference(y).map(|key| *key).collect();
+    try!(check_int_set(&a.difference(b), &difference).map_err(|err| format!("difference: {}", err)));
+
+    Ok(())
+}
+
+// Run a sequence of operations starting from an empty integer set,
+// describing the first step at which the set and the model disagree.
+fn run_int_set(ops: &[SetOp]) -> Result<(), String> {
+    let mut set = IntSet::new();
+    let mut model = BTreeSet::new();
+    let mut versions = vec![(set.clone(), model.clone())];
+
+    for (step, op) in ops.iter().enumerate() {
+        set = apply_int_set(&set, &mut model, op);
+
+        let checked = check_int_set(&set, &model).and_then(|()| {
+            let (ref old, ref old_model) = versions[versions.len() / 2];
+            check_int_set_ops(&set, old, &model, old_model)
+        });
+
+        match checked {
+            Ok(()) => {},
+            Err(err) => return Err(format!("step {} ({}): {}", step, op, err))
+        }
+
+        versions.push((set.clone(), model.clone()));
+    }
+
+    check_versions(versions.as_slice(), |set, model| check_int_set(set, model))
+}
+
+#[deriving(Clone, Show)]
+enum TrieOp {
+    Put(Vec<u8>, uint),
+    Take(Vec<u8>)
+}
+
+// Keys are short strings over a small alphabet, so that they often share
+// prefixes and are often prefixes of each other.
+fn random_trie_key<R: Rng>(rng: &mut R) -> Vec<u8> {
+    let len = rng.gen_range(0u, 5);
+    Vec::from_fn(len, |_| rng.gen_range(0u8, 3))
+}
+
+fn random_trie_op<R: Rng>(rng: &mut R) -> TrieOp {
+    let key = random_trie_key(rng);
+    if rng.gen_range(0u, 3) == 0 { Take(key) } else { Put(key, rng.gen()) }
+}
+
+// Apply an operation to the model, and to the trie to get its next version.
+// Takes of keys with an even length go through `remove`, whose value is
+// checked against the model, and the rest through `delete`.
+fn apply_trie(trie: &Trie<u8, uint>, model: &mut BTreeMap<Vec<u8>, uint>, op: &TrieOp)
+        -> Result<Trie<u8, uint>, String> {
+    match *op {
+        Put(ref key, value) => {
+            model.insert(key.clone(), value);
+            Ok(trie.insert(key.as_slice(), Arc::new(value)))
+        },
+        Take(ref key) => {
+            let expected = model.remove(key);
+            if key.len() % 2 == 1 { return Ok(trie.delete(key.as_slice())) }
+
+            let (removed, rest) = match trie.remove(key.as_slice()) {
+                Some((value, rest)) => (Some(*value), rest),
+                None => (None, trie.clone())
+            };
+
+            if removed != expected {
+                return Err(format!("remove({}) gave {}, expected {}", key, removed, expected))
+            }
+            Ok(rest)
+        }
+    }
+}
+
+// Check a trie against the model, including its prefix queries and the
+// subtrie for `probe`.
+fn check_trie(trie: &Trie<u8, uint>, model: &BTreeMap<Vec<u8>, uint>, probe: &[u8]) -> Result<(), String> {
+    if trie.len() != model.len() {
+        return Err(format!("len is {}, expected {}", trie.len(), model.len()))
+    }
+
+    let actual: Vec<(Vec<u8>, uint)> = trie.iter().map(|(k, v)| (k, *v)).collect();
+    let expected: Vec<(Vec<u8>, uint)> = model.iter().map(|(k, v)| (k.clone(), *v)).collect();
+    if actual != expected {
+        return Err(format!("contents are {}, expected {}", actual, expected))
+    }
+
+    let actual: Vec<(Vec<u8>, uint)> = trie.iter_prefix(probe).map(|(k, v)| (k, *v)).collect();
+    let expected: Vec<(Vec<u8>, uint)> = expected.into_iter()
+        .filter(|&(ref k, _)| k.as_slice().starts_with(probe))
+        .collect();
+    if actual != expected {
+        return Err(format!("iter_prefix({}) is {}, expected {}", probe, actual, expected))
+    }
+
+    let expected: Vec<(Vec<u8>, uint)> = expected.iter()
+        .map(|&(ref k, v)| (k.slice_from(probe.len()).to_vec(), v))
+        .collect();
+    match trie.subtrie(probe) {
+        Some(subtrie) => {
+            let actual: Vec<(Vec<u8>, uint)> = subtrie.iter().map(|(k, v)| (k, *v)).collect();
+            if actual != expected || subtrie.len() != expected.len() {
+                return Err(format!("subtrie({}) is {} with len {}, expected {}",
+                                   probe, actual, subtrie.len(), expected))
+            }
+        },
+        // Only the empty prefix always has a subtrie.
+        None => {
+            if probe.is_empty() || !expected.is_empty() {
+                return Err(format!("subtrie({}) is missing, expected {}", probe, expected))
+            }
+        }
+    }
+
+    let longest = range(0, probe.len() + 1).rev()
+        .filter_map(|len| model.get(&probe.slice_to(len).to_vec()).map(|value| (len, value)))
+        .next();
+    if trie.longest_prefix_match(probe) != longest {
+        return Err(format!("longest_prefix_match({}) is {}, expected {}",
+                           probe, trie.longest_prefix_match(probe), longest))
+    }
+
+    Ok(())
+}
+
+// Run a sequence of operations starting from an empty trie, describing the
+// first step at which the trie and the model disagree. The key of each
+// operation is also used to probe the prefix queries.
+fn run_trie(ops: &[TrieOp]) -> Result<(), String> {
+    let mut trie = Trie::new();
+    let mut model = BTreeMap::new();
+    let mut versions = Vec::new();
+
+    for (step, op) in ops.iter().enumerate() {
+        trie = match apply_trie(&trie, &mut model, op) {
+            Ok(next) => next,
+            Err(err) => return Err(format!("step {} ({}): {}", step, op, err))
+        };
+
+        let probe = match *op { Put(ref key, _) | Take(ref key) => key.clone() };
+        match check_trie(&trie, &model, probe.as_slice()) {
+            Ok(()) => versions.push(((trie.clone(), probe), model.clone())),
+            Err(err) => return Err(format!("step {} ({}): {}", step, op, err))
+        }
+    }
+
+    check_versions(versions.as_slice(), |&(ref trie, ref probe), model| {
+        check_trie(trie, model, probe.as_slice())
+    })
+}
+
+#[deriving(Clone, Show)]
+enum HeapOp {
+    Enqueue(uint),
+    Dequeue,
+    Meld(Vec<uint>)
+}
+
+fn random_heap_op<R: Rng>(rng: &mut R) -> HeapOp {
+    match rng.gen_range(0u, 8) {
+        0 | 1 => Dequeue,
+        2 => {
+            let len = rng.gen_range(0u, 8);
+            Meld(Vec::from_fn(len, |_| rng.gen_range(0, KEYS)))
+        },
+        _ => Enqueue(rng.gen_range(0, KEYS))
+    }
+}
+
+// A heap, and a queue which pops the smallest priority first and one which
+// pops the largest first, all built from the same values.
+#[deriving(Clone)]
+struct Heaps {
+    heap: Heap<uint>,
+    ascending: PriorityQueue<uint, uint>,
+    descending: PriorityQueue<uint, uint, Descending>
+}
+
+// Both models are kept sorted in ascending order. The heap and the ascending
+// queue pop from the front of the first model, and the descending queue pops
+// from the back of the second.
+#[deriving(Clone)]
+struct HeapModel {
+    ascending: Vec<uint>,
+    descending: Vec<uint>
+}
+
+impl HeapModel {
+    fn add(&mut self, value: uint) {
+        let index = self.ascending.iter().take_while(|&&x| x < value).count();
+        self.ascending.insert(index, value);
+        let index = self.descending.iter().take_while(|&&x| x < value).count();
+        self.descending.insert(index, value);
+    }
+}
+
+// Apply an operation to the model, and to the heap and queues to get their
+// next versions, checking the values they pop against the model. Melding
+// builds the new values into a heap and queues of their own with `collect`,
+// and merges those in.
+fn apply_heap(heaps: &Heaps, model: &mut HeapModel, op: &HeapOp) -> Result<Heaps, String> {
+    match *op {
+        Enqueue(value) => {
+            model.add(value);
+
+            Ok(Heaps {
+                heap: heaps.heap.push(Arc::new(value)),
+                ascending: heaps.ascending.push(value, value),
+                descending: heaps.descending.push(value, value)
+            })
+        },
+        Meld(ref values) => {
+            for &value in values.iter() { model.add(value); }
+
+            let heap: Heap<uint> = values.iter().map(|&value| value).collect();
+            let ascending: PriorityQueue<uint, uint> = values.iter().map(|&value| (value, value)).collect();
+            let descending: PriorityQueue<uint, uint, Descending> = values.iter()
+                .map(|&value| (value, value))
+                .collect();
+
+            Ok(Heaps {
+                heap: heaps.heap.merge(&heap),
+                ascending: heaps.ascending.merge(&ascending),
+                descending: heaps.descending.merge(&descending)
+            })
+        },
+        Dequeue => {
+            let min = if model.ascending.is_empty() { None } else { Some(model.ascending.remove(0)) };
+            let max = model.descending.pop();
+
+            let heap = match heaps.heap.pop() {
+                Some((value, rest)) => {
+                    if Some(*value) != min { return Err(format!("heap popped {}, expected {}", value, min)) }
+                    rest
+                },
+                None => {
+                    if min.is_some() { return Err(format!("heap popped nothing, expected {}", min)) }
+                    heaps.heap.clone()
+                }
+            };
+
+            Ok(Heaps {
+                heap: heap,
+                ascending: try!(pop_queue(&heaps.ascending, min).map_err(|err| format!("ascending {}", err))),
+                descending: try!(pop_queue(&heaps.descending, max).map_err(|err| format!("descending {}", err)))
+            })
+        }
+    }
+}
+
+// Pop the next pair from a queue, which should have the priority `expected`.
+fn pop_queue<C: Compare<uint> + Clone>(queue: &PriorityQueue<uint, uint, C>, expected: Option<uint>)
+        -> Result<PriorityQueue<uint, uint, C>, String> {
+    match queue.pop() {
+        Some((&priority, &value, rest)) => {
+            if Some(priority) != expected || value != priority {
+                return Err(format!("queue popped {}: {}, expected {}", priority, value, expected))
+            }
+            Ok(rest)
+        },
+        None => {
+            if expected.is_some() { return Err(format!("queue popped nothing, expected {}", expected)) }
+            Ok(queue.clone())
+        }
+    }
+}
+
+// Check that a queue pops the priorities in `expected`, in order.
+fn check_queue<C: Compare<uint> + Clone>(queue: &PriorityQueue<uint, uint, C>, expected: Vec<uint>)
+        -> Result<(), String> {
+    if queue.len() != expected.len() {
+        return Err(format!("len is {}, expected {}", queue.len(), expected.len()))
+    }
+
+    let peeked = queue.peek().map(|(&priority, _)| priority);
+    if peeked != expected.iter().next().map(|&x| x) {
+        return Err(format!("peeked {}, expected {}", peeked, expected.iter().next()))
+    }
+
+    let mut queue = queue.clone();
+    let mut actual = Vec::new();
+
+    loop {
+        let rest = match queue.pop() {
+            Some((&priority, _, rest)) => {
+                actual.push(priority);
+                rest
+            },
+            None => break
+        };
+
+        queue = rest;
+    }
+
+    if actual != expected {
+        return Err(format!("popped {}, expected {}", actual, expected))
+    }
+
+    Ok(())
+}
+
+fn check_heap(heaps: &Heaps, model: &HeapModel) -> Result<(), String> {
+    if heaps.heap.len() != model.ascending.len() {
+        return Err(format!("heap len is {}, expected {}", heaps.heap.len(), model.ascending.len()))
+    }
+
+    let actual: Vec<uint> = heaps.heap.iter().map(|value| *value).collect();
+    if actual != model.ascending {
+        return Err(format!("heap contents are {}, expected {}", actual, model.ascending))
+    }
+
+    // Collecting values in descending order gives the heap with the longest
+    // left spine.
+    let built: Heap<uint> = model.ascending.iter().rev().map(|&x| x).collect();
+    let actual: Vec<uint> = built.iter().map(|value| *value).collect();
+    if actual != model.ascending {
+        return Err(format!("collected heap contents are {}, expected {}", actual, model.ascending))
+    }
+
+    try!(check_queue(&heaps.ascending, model.ascending.clone())
+         .map_err(|err| format!("ascending queue: {}", err)));
+
+    let expected: Vec<uint> = model.descending.iter().rev().map(|&x| x).collect();
+    try!(check_queue(&heaps.descending, expected).map_err(|err| format!("descending queue: {}", err)));
+
+    Ok(())
+}
+
+// Run a sequence of operations starting from an empty heap and queues,
+// describing the first step at which any of them disagrees with the model.
+fn run_heap(ops: &[HeapOp]) -> Result<(), String> {
+    let mut heaps = Heaps {
+        heap: Heap::new(),
+        ascending: PriorityQueue::new(),
+        descending: PriorityQueue::with_comparator(Descending)
+    };
+    let mut model = HeapModel { ascending: Vec::new(), descending: Vec::new() };
+    let mut versions = Vec::new();
+
+    for (step, op) in ops.iter().enumerate() {
+        let result = apply_heap(&heaps, &mut model, op)
+            .and_then(|next| check_heap(&next, &model).map(|()| next));
+
+        match result {
+            Ok(next) => {
+                heaps = next;
+                versions.push((heaps.clone(), model.clone()));
+            },
+            Err(err) => return Err(format!("step {} ({}): {}", step, op, err))
+        }
+    }
+
+    check_versions(versions.as_slice(), |heaps, model| check_heap(heaps, model))
+}
+
+#[deriving(Clone, Show)]
+enum ListOp {
+    Push(uint),
+    PushFront(uint),
+    Pop
+}
+
+fn random_list_op<R: Rng>(rng: &mut R) -> ListOp {
+    match rng.gen_range(0u, 6) {
+        0 | 1 => Pop,
+        2 => PushFront(rng.gen()),
+        _ => Push(rng.gen())
+    }
+}
+
+// Apply an operation to the model, whose first element is the head of the
+// list, and to the list to get its next version. Push shares the list as the
+// new tail with `cons`, while PushFront moves a copy of its head into a new
+// list with `push_front`.
+fn apply_list(list: &Arc<List<uint>>, model: &mut Vec<uint>, op: &ListOp) -> Result<Arc<List<uint>>, String> {
+    match *op {
+        Push(value) => {
+            model.insert(0, value);
+            Ok(Arc::new(list.cons(value)))
+        },
+        PushFront(value) => {
+            model.insert(0, value);
+            Ok(Arc::new((**list).clone().push_front(value)))
+        },
+        Pop => {
+            let expected = if model.is_empty() { None } else { Some(model.remove(0)) };
+
+            match list.pop_front() {
+                Some((&head, tail)) => {
+                    if Some(head) != expected {
+                        return Err(format!("pop_front gave {}, expected {}", head, expected))
+                    }
+                    Ok(tail)
+                },
+                None => {
+                    if expected.is_some() {
+                        return Err(format!("pop_front gave nothing, expected {}", expected))
+                    }
+                    Ok(list.clone())
+                }
+            }
+        }
+    }
+}
+
+fn check_list(list: &List<uint>, model: &Vec<uint>) -> Result<(), String> {
+    let actual: Vec<uint> = list.iter().map(|value| *value).collect();
+    if actual != *model {
+        return Err(format!("contents are {}, expected {}", actual, model))
+    }
+
+    if list.len() != model.len() || list.is_empty() != model.is_empty() {
+        return Err(format!("len is {}, expected {}", list.len(), model.len()))
+    }
+
+    if list.head() != model.iter().next() {
+        return Err(format!("head is {}, expected {}", list.head(), model.iter().next()))
+    }
+
+    Ok(())
+}
+
+// Check the comparisons, hashes and formatting of two lists against those of
+// their models.
+fn check_list_compare(a: &List<uint>, b: &List<uint>, x: &Vec<uint>, y: &Vec<uint>) -> Result<(), String> {
+    if (a == b) != (x == y) {
+        return Err(format!("== is {}, expected {}", a == b, x == y))
+    }
+
+    if a.cmp(b) != x.cmp(y) {
+        return Err(format!("cmp is {}, expected {}", a.cmp(b), x.cmp(y)))
+    }
+
+    if a.partial_cmp(b) != x.partial_cmp(y) {
+        return Err(format!("partial_cmp is {}, expected {}", a.partial_cmp(b), x.partial_cmp(y)))
+    }
+
+    let same_hash = sip::hash(a) == sip::hash(b);
+    if same_hash != (sip::hash(x) == sip::hash(y)) {
+        return Err(format!("hashes are {}, expected {}",
+                           if same_hash { "equal" } else { "different" },
+                           if same_hash { "different" } else { "equal" }))
+    }
+
+    if format!("{}", a) != format!("{}", x) {
+        return Err(format!("formatted as {}, expected {}", a, x))
+    }
+
+    Ok(())
+}
+
+// Check the traits of a list against those of its model, comparing it with a
+// version from earlier in the sequence, with a copy of that version collected
+// from scratch, and with lists which share it as their tail.
+fn check_list_traits(a: &Arc<List<uint>>, b: &Arc<List<uint>>, x: &Vec<uint>, y: &Vec<uint>, probe: uint)
+        -> Result<(), String> {
+    let cloned = (**a).clone();
+    try!(check_list(&cloned, x).map_err(|err| format!("clone: {}", err)));
+
+    let collected: List<uint> = y.iter().map(|value| *value).collect();
+    try!(check_list(&collected, y).map_err(|err| format!("collect: {}", err)));
+
+    let mut extended = (**a).clone();
+    extended.extend(y.iter().map(|value| *value));
+    let mut appended = x.clone();
+    appended.push_all(y.as_slice());
+    try!(check_list(&extended, &appended).map_err(|err| format!("extend: {}", err)));
+    try!(check_list(&**a, x).map_err(|err| format!("extend changed the original list: {}", err)));
+
+    let (first, second) = (a.cons(probe), a.cons(!probe));
+    let (mut first_model, mut second_model) = (x.clone(), x.clone());
+    first_model.insert(0, probe);
+    second_model.insert(0, !probe);
+
+    let lists = [("new", &**a, x), ("old", &**b, y), ("collected old", &collected, y),
+                 ("consed", &first, &first_model), ("consed again", &second, &second_model)];
+    for &(name, list, model) in lists.iter() {
+        for &(other, other_list, other_model) in lists.iter() {
+            try!(check_list_compare(list, other_list, model, other_model)
+                 .map_err(|err| format!("{} against {}: {}", name, other, err)));
+        }
+    }
+
+    Ok(())
+}
+
+// Run a sequence of operations starting from an empty list, describing the
+// first step at which the list and the model disagree.
+fn run_list(ops: &[ListOp]) -> Result<(), String> {
+    let mut list = Arc::new(List::new());
+    let mut model = Vec::new();
+    let mut versions = vec![(list.clone(), model.clone())];
+
+    for (step, op) in ops.iter().enumerate() {
+        let result = apply_list(&list, &mut model, op)
+            .and_then(|next| check_list(&*next, &model).map(|()| next))
+            .and_then(|next| {
+                let (ref old, ref old_model) = versions[versions.len() / 2];
+                check_list_traits(&next, old, &model, old_model, step).map(|()| next)
+            });
+
+        match result {
+            Ok(next) => {
+                list = next;
+                versions.push((list.clone(), model.clone()));
+            },
+            Err(err) => return Err(format!("step {} ({}): {}", step, op, err))
+        }
+    }
+
+    check_versions(versions.as_slice(), |list, model| check_list(&**list, model))
+}
+
+// Apply an operation to the model, whose first element is the top of the
+// stack, and to the stack to get its next version. Stacks have a single way
+// to push, so both pushes use it.
+fn apply_stack(stack: &Stack<uint>, model: &mut Vec<uint>, op: &ListOp) -> Result<Stack<uint>, String> {
+    match *op {
+        Push(value) | PushFront(value) => {
+            model.insert(0, value);
+            Ok(stack.push(value))
+        },
+        Pop => {
+            let expected = if model.is_empty() { None } else { Some(model.remove(0)) };
+
+            match stack.pop() {
+                Some((&top, rest)) => {
+                    if Some(top) != expected {
+                        return Err(format!("pop gave {}, expected {}", top, expected))
+                    }
+                    Ok(rest)
+                },
+                None => {
+                    if expected.is_some() {
+                        return Err(format!("pop gave nothing, expected {}", expected))
+                    }
+                    Ok(stack.clone())
+                }
+            }
+        }
+    }
+}
+
+fn check_stack(stack: &Stack<uint>, model: &Vec<uint>) -> Result<(), String> {
+    if stack.len() != model.len() || stack.is_empty() != model.is_empty() {
+        return Err(format!("len is {}, expected {}", stack.len(), model.len()))
+    }
+
+    if stack.peek() != model.iter().next() {
+        return Err(format!("peek is {}, expected {}", stack.peek(), model.iter().next()))
+    }
+
+    let actual: Vec<uint> = stack.iter().map(|value| *value).collect();
+    if actual != *model {
+        return Err(format!("contents are {}, expected {}", actual, model))
+    }
+
+    check_list(stack.as_list(), model).map_err(|err| format!("as_list: {}", err))
+}
+
+// Run a sequence of operations starting from an empty stack, describing the
+// first step at which the stack and the model disagree.
+fn run_stack(ops: &[ListOp]) -> Result<(), String> {
+    let mut stack = Stack::new();
+    let mut model = Vec::new();
+    let mut versions = Vec::new();
+
+    for (step, op) in ops.iter().enumerate() {
+        let result = apply_stack(&stack, &mut model, op)
+            .and_then(|next| check_stack(&next, &model).map(|()| next));
+
+        match result {
+            Ok(next) => {
+                stack = next;
+                versions.push((stack.clone(), model.clone()));
+            },
+            Err(err) => return Err(format!("step {} ({}): {}", step, op, err))
+        }
+    }
+
+    check_versions(versions.as_slice(), |stack, model| check_stack(stack, model))
+}
+
+// Shrink a failing sequence of operations by removing one operation at a
+// time, for as long as the sequence keeps failing.
+fn shrink<T: Clone>(ops: Vec<T>, fails: |&[T]| -> bool) -> Vec<T> {
+    let mut ops = ops;
+    let mut i = 0;
+
+    while i < ops.len() {
+        let mut candidate = ops.clone();
+        candidate.remove(i);
+
+        if fails(candidate.as_slice()) {
+            ops = candidate;
+        } else {
+            i += 1;
+        }
+    }
+
+    ops
+}
+
+// Check a structure against its model on many random sequences of
+// operations drawn from `gen_op`, panicking with a shrunk sequence and the
+// first disagreement `run` found in it if any sequence fails.
+fn check_model<T: Clone + Show>(name: &str, gen_op: |&mut XorShiftRng| -> T, run: |&[T]| -> Result<(), String>) {
+    let mut rng: XorShiftRng = SeedableRng::from_seed([0x1234, 0x5678, 0x9abc, 0xdef0]);
+
+    for _ in range(0, RUNS) {
+        let ops = Vec::from_fn(STEPS, |_| gen_op(&mut rng));
+
+        if run(ops.as_slice()).is_err() {
+            let ops = shrink(ops, |ops| run(ops).is_err());
+            panic!("{}: {}\nMinimal operations: {}", name, run(ops.as_slice()).unwrap_err(), ops);
+        }
+    }
+}
+
+// Check every version kept while running a sequence against its model
+// again, to make sure that later operations did not change it.
+//
+// The runners also combine each new version with the one from halfway
+// through the sequence so far, which shares some of its structure, so that
+// both the shortcuts for shared subtrees and the general case get exercised.
+fn check_versions<S, M>(versions: &[(S, M)], check: |&S, &M| -> Result<(), String>) -> Result<(), String> {
+    for (i, &(ref version, ref model)) in versions.iter().enumerate() {
+        try!(check(version, model).map_err(|err| format!("version {} was changed: {}", i, err)));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn map_matches_btreemap() {
+    check_model("Map disagrees with BTreeMap", |rng| random_map_op(rng), |ops| run_map(ops));
+}
+
+#[test]
+fn hash_map_matches_btreemap() {
+    check_model("HashMap disagrees with BTreeMap", |rng| random_map_op(rng), |ops| run_hash_map(ops));
+}
+
+#[test]
+fn int_map_matches_btreemap() {
+    check_model("IntMap disagrees with BTreeMap", |rng| random_map_op(rng), |ops| run_int_map(ops));
+}
+
+#[test]
+fn set_matches_btreeset() {
+    check_model("Set disagrees with BTreeSet", |rng| random_set_op(rng), |ops| run_set(ops));
+}
+
+#[test]
+fn int_set_matches_btreeset() {
+    check_model("IntSet disagrees with BTreeSet", |rng| random_set_op(rng), |ops| run_int_set(ops));
+}
+
+#[test]
+fn trie_matches_btreemap() {
+    check_model("Trie disagrees with BTreeMap", |rng| random_trie_op(rng), |ops| run_trie(ops));
+}
+
+#[test]
+fn heap_matches_sorted_vec() {
+    check_model("Heap disagrees with a sorted Vec", |rng| random_heap_op(rng), |ops| run_heap(ops));
+}
+
+#[test]
+fn list_matches_vec() {
+    check_model("List disagrees with Vec", |rng| random_list_op(rng), |ops| run_list(ops));
+}
+
+#[test]
+fn stack_matches_vec() {
+    check_model("Stack disagrees with Vec", |rng| random_list_op(rng), |ops| run_stack(ops));
+}